@@ -0,0 +1,203 @@
+use crate::*;
+
+//permissions the owner can delegate. The owner can do everything a role allows
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    //manages the whitelist of TokenizedCards and the accepted fungible tokens
+    WhitelistManager,
+    //changes the fee and the fee recipient
+    FeeManager,
+}
+
+#[near_bindgen]
+impl Contract {
+    //gives a role to an account. Owner only
+    #[payable]
+    pub fn grant_role(&mut self, account_id: AccountId, role: Role) {
+        //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
+        assert_one_yocto();
+        self.assert_owner();
+
+        let mut roles = self.roles.get(&account_id).unwrap_or_default();
+        if !roles.contains(&role) {
+            roles.push(role);
+            self.roles.insert(&account_id, &roles);
+        }
+
+        MarketEvent::RoleGrant([RoleUpdate {
+            account_id,
+            role,
+            updated_by: env::predecessor_account_id(),
+        }])
+        .emit();
+    }
+
+    //takes a role away from an account. Owner only
+    #[payable]
+    pub fn revoke_role(&mut self, account_id: AccountId, role: Role) {
+        //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
+        assert_one_yocto();
+        self.assert_owner();
+
+        let mut roles = self.roles.get(&account_id).unwrap_or_default();
+        roles.retain(|r| *r != role);
+        if roles.is_empty() {
+            self.roles.remove(&account_id);
+        } else {
+            self.roles.insert(&account_id, &roles);
+        }
+
+        MarketEvent::RoleRevoke([RoleUpdate {
+            account_id,
+            role,
+            updated_by: env::predecessor_account_id(),
+        }])
+        .emit();
+    }
+
+    //proposes a new owner. The ownership only moves once they call accept_ownership. Owner only
+    #[payable]
+    pub fn propose_owner(&mut self, new_owner_id: AccountId) {
+        //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
+        assert_one_yocto();
+        self.assert_owner();
+        self.pending_owner_id = Some(new_owner_id.clone());
+
+        MarketEvent::OwnershipProposal([OwnershipUpdate {
+            old_owner_id: self.owner_id.clone(),
+            new_owner_id,
+        }])
+        .emit();
+    }
+
+    //makes the proposed owner the owner of the market
+    #[payable]
+    pub fn accept_ownership(&mut self) {
+        //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
+        assert_one_yocto();
+        let new_owner_id = env::predecessor_account_id();
+        assert_eq!(self.pending_owner_id.as_ref(), Some(&new_owner_id), "Only the proposed owner can accept the ownership");
+
+        self.pending_owner_id = None;
+        let old_owner_id = std::mem::replace(&mut self.owner_id, new_owner_id.clone());
+
+        MarketEvent::OwnershipTransfer([OwnershipUpdate {
+            old_owner_id,
+            new_owner_id,
+        }])
+        .emit();
+    }
+
+    //changes the default fee taken from every sale, in basis points. Owner or fee manager only
+    #[payable]
+    pub fn set_fee(&mut self, fee_bps: u16) {
+        //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
+        assert_one_yocto();
+        self.assert_role(Role::FeeManager);
+        assert!(fee_bps <= MAX_FEE_BPS, "Fee can't be more than {} basis points", MAX_FEE_BPS);
+
+        let old_fee_bps = std::mem::replace(&mut self.fee_bps, fee_bps);
+
+        MarketEvent::FeeUpdate([FeeUpdate {
+            old_fee_bps,
+            new_fee_bps: fee_bps,
+            updated_by: env::predecessor_account_id(),
+        }])
+        .emit();
+    }
+
+    //replaces the volume based fee tiers. Owner or fee manager only
+    #[payable]
+    pub fn set_fee_tiers(&mut self, fee_tiers: Vec<FeeTier>) {
+        //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
+        assert_one_yocto();
+        self.assert_role(Role::FeeManager);
+        for tier in &fee_tiers {
+            assert!(tier.fee_bps <= MAX_FEE_BPS, "Fee can't be more than {} basis points", MAX_FEE_BPS);
+        }
+
+        self.fee_tiers = fee_tiers.clone();
+
+        MarketEvent::FeeTiersUpdate([FeeTiersUpdate {
+            fee_tiers,
+            updated_by: env::predecessor_account_id(),
+        }])
+        .emit();
+    }
+
+    //changes where the fee is sent. Owner or fee manager only
+    #[payable]
+    pub fn set_fee_recipient(&mut self, fee_recipient: AccountId) {
+        //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
+        assert_one_yocto();
+        self.assert_role(Role::FeeManager);
+
+        let old_fee_recipient = std::mem::replace(&mut self.fee_recipient, fee_recipient.clone());
+
+        MarketEvent::FeeRecipientUpdate([FeeRecipientUpdate {
+            old_fee_recipient,
+            new_fee_recipient: fee_recipient,
+            updated_by: env::predecessor_account_id(),
+        }])
+        .emit();
+    }
+
+    //views
+
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+
+    pub fn get_pending_owner(&self) -> Option<AccountId> {
+        self.pending_owner_id.clone()
+    }
+
+    //returns the roles given to an account
+    pub fn get_roles(&self, account_id: AccountId) -> Vec<Role> {
+        self.roles.get(&account_id).unwrap_or_default()
+    }
+
+    //returns paginated accounts that were given roles, with their roles
+    pub fn get_role_members(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<(AccountId, Vec<Role>)> {
+        //where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        self.roles
+            .iter()
+            .skip(start as usize)
+            .take(limit.unwrap_or(0) as usize)
+            .collect()
+    }
+
+    //default fee in basis points
+    pub fn get_fee(&self) -> u16 {
+        self.fee_bps
+    }
+
+    pub fn get_fee_tiers(&self) -> Vec<FeeTier> {
+        self.fee_tiers.clone()
+    }
+
+    pub fn get_fee_recipient(&self) -> AccountId {
+        self.fee_recipient.clone()
+    }
+}
+
+impl Contract {
+    //makes sure the caller is the owner of the market
+    pub(crate) fn assert_owner(&self) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "Only the owner can call this method");
+    }
+
+    //makes sure the caller is the owner of the market or was given the role
+    pub(crate) fn assert_role(&self, role: Role) {
+        let caller_id = env::predecessor_account_id();
+        assert!(
+            caller_id == self.owner_id || self.roles.get(&caller_id).is_some_and(|roles| roles.contains(&role)),
+            "Only the owner or a {:?} can call this method",
+            role
+        );
+    }
+}
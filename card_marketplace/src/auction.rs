@@ -0,0 +1,151 @@
+use crate::*;
+
+//a bid placed on an auction. The amount is held by the market until the bidder is outbid or the auction settles
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Bid {
+    pub bidder_id: AccountId,
+    pub amount: U128,
+}
+
+//state of an english auction. The reserve price is the sale's price
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Auction {
+    //when bidding opens (nanoseconds)
+    pub start_at: U64,
+    //when bidding closes (nanoseconds). Late bids push it back
+    pub end_at: U64,
+    //how much a bid has to beat the current highest bid by
+    pub min_bid_increment: U128,
+    //current highest bid, if any
+    pub highest_bid: Option<Bid>,
+}
+
+//auction parameters passed in the SaleArgs msg when listing through nft_approve
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuctionArgs {
+    //defaults to now
+    pub start_at: Option<U64>,
+    pub end_at: U64,
+    //defaults to 1
+    pub min_bid_increment: Option<U128>,
+}
+
+impl From<AuctionArgs> for Auction {
+    fn from(args: AuctionArgs) -> Self {
+        let start_at = args.start_at.map(|start_at| start_at.0).unwrap_or_else(env::block_timestamp);
+        //make sure the auction ends after it starts and hasn't already ended
+        assert!(
+            args.end_at.0 > start_at.max(env::block_timestamp()),
+            "Auction must end after it starts and in the future"
+        );
+
+        Auction {
+            start_at: U64(start_at),
+            end_at: args.end_at,
+            min_bid_increment: args.min_bid_increment.unwrap_or(U128(1)),
+            highest_bid: None,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    //bids on an auction listed in NEAR with the attached deposit. The previous highest bidder is refunded
+    #[payable]
+    pub fn place_bid(&mut self, nft_contract_id: AccountId, token_id: TokenId) {
+        //get the unique sale ID (contract + DELIMITER + token ID)
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        //get the sale object from the unique sale ID. If the sale doesn't exist, panic.
+        let sale = self.sales.get(&contract_and_token_id).expect("No sale");
+
+        //auctions listed in a fungible token take bids through ft_transfer_call
+        assert_eq!(sale.currency, Currency::Near, "This auction takes bids in {:?}", sale.currency);
+        self.assert_whitelisted(&nft_contract_id);
+
+        self.internal_place_bid(
+            &contract_and_token_id,
+            sale,
+            env::predecessor_account_id(),
+            env::attached_deposit(),
+        );
+    }
+
+    //ends an auction once its end time has passed. Can be called by anyone.
+    //the highest bid buys the token through the usual purchase flow, without bids the listing is simply removed
+    pub fn settle_auction(&mut self, nft_contract_id: AccountId, token_id: TokenId) {
+        //get the unique sale ID (contract + DELIMITER + token ID)
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        //get the sale object from the unique sale ID. If the sale doesn't exist, panic.
+        let sale = self.sales.get(&contract_and_token_id).expect("No sale");
+
+        let auction = match sale.sale_type {
+            SaleType::Auction(auction) => auction,
+            _ => env::panic_str("This sale is not an auction"),
+        };
+        assert!(env::block_timestamp() >= auction.end_at.0, "The auction hasn't ended yet");
+        //the bid of a delisted collection is refunded by purge_collection
+        self.assert_whitelisted(&nft_contract_id);
+
+        match auction.highest_bid {
+            //the bid was escrowed so a failed transfer has to send it back to the bidder
+            Some(Bid { bidder_id, amount }) => {
+                let ft_token_id = match sale.currency {
+                    Currency::FungibleToken(ft_token_id) => Some(ft_token_id),
+                    Currency::Near => None,
+                };
+                self.process_purchase(nft_contract_id, token_id, amount, amount, bidder_id, ft_token_id, true, GAS_FOR_RESOLVE_PURCHASE);
+            }
+            //a token held by the market goes back to the owner
+            None => {
+                self.internal_drop_sale(nft_contract_id, token_id);
+            }
+        }
+    }
+}
+
+impl Contract {
+    //records a bid on the given auction sale. `amount` has already been received by the market in the sale's currency
+    pub(crate) fn internal_place_bid(
+        &mut self,
+        contract_and_token_id: &ContractAndTokenId,
+        mut sale: Sale,
+        bidder_id: AccountId,
+        amount: Balance,
+    ) {
+        let auction = match &mut sale.sale_type {
+            SaleType::Auction(auction) => auction,
+            _ => env::panic_str("This sale is not an auction"),
+        };
+        assert_ne!(sale.owner_id, bidder_id, "Cannot bid on your own sale.");
+
+        //make sure the auction is running
+        let now = env::block_timestamp();
+        assert!(now >= auction.start_at.0, "The auction hasn't started yet");
+        assert!(now < auction.end_at.0, "The auction has ended");
+
+        //the first bid has to meet the reserve price, the next ones have to beat the highest bid by the increment
+        let min_bid = match &auction.highest_bid {
+            Some(bid) => bid.amount.0 + auction.min_bid_increment.0.max(1),
+            None => sale.price.0,
+        };
+        assert!(amount >= min_bid, "Bid must be at least {}", min_bid);
+
+        //refund the bidder that was just outbid
+        let new_bid = Bid { bidder_id, amount: U128(amount) };
+        if let Some(outbid) = auction.highest_bid.replace(new_bid) {
+            self.internal_refund(&sale.currency, outbid.bidder_id, outbid.amount.0);
+        }
+
+        //a bid in the last minutes extends the auction so nobody can snipe it
+        if auction.end_at.0 - now < AUCTION_EXTENSION {
+            auction.end_at = U64(now + AUCTION_EXTENSION);
+        }
+
+        self.sales.insert(contract_and_token_id, &sale);
+
+        MarketEvent::ListingUpdate([(&sale).into()]).emit();
+    }
+}
@@ -0,0 +1,476 @@
+use crate::*;
+
+//unique ID of a bundle
+pub type BundleId = u64;
+
+//the most tokens a bundle can hold. Every item is transferred in the same transaction so this is limited by GAS:
+//buying a bundle of 5 tokens takes 225 TGas (see `Contract::bundle_purchase_gas`)
+pub const MAX_BUNDLE_SIZE: usize = 5;
+
+//a token in a bundle, with the market's approval ID to transfer it on behalf of the owner
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BundleItem {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub approval_id: u64,
+}
+
+//several tokens of the same owner sold together for one price in NEAR
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Bundle {
+    pub bundle_id: U64,
+    //owner of the bundle / tokens
+    pub owner_id: AccountId,
+    pub items: Vec<BundleItem>,
+    //price of the whole bundle
+    pub price: U128,
+    //the bundle can't be bought anymore after this time (nanoseconds) and can be pruned by anyone
+    pub expires_at: Option<U64>,
+}
+
+impl Bundle {
+    //splits the price over the items to get the payout of each token
+    pub fn item_prices(&self) -> Vec<Balance> {
+        split_price(self.price.0, self.items.len())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| env::block_timestamp() >= expires_at.0)
+    }
+}
+
+//splits a price in `count` equal shares. The last one gets the rounding remainder
+fn split_price(price: Balance, count: usize) -> Vec<Balance> {
+    let share = price / count as u128;
+    let mut prices = vec![share; count];
+    prices[count - 1] = price - share * (count as u128 - 1);
+    prices
+}
+
+//a token to put in a bundle. It has to be listed by the caller already
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BundleItemArgs {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+}
+
+#[near_bindgen]
+impl Contract {
+    //groups tokens the caller listed through nft_approve into a bundle sold for one price. The listings are
+    //taken down and their approvals are used to transfer the tokens. Returns the bundle ID
+    #[payable]
+    pub fn list_bundle(&mut self, items: Vec<BundleItemArgs>, price: U128, expires_at: Option<U64>) -> U64 {
+        //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
+        assert_one_yocto();
+        assert!(
+            items.len() >= 2 && items.len() <= MAX_BUNDLE_SIZE,
+            "A bundle must have between 2 and {} tokens",
+            MAX_BUNDLE_SIZE
+        );
+        assert!(price.0 > 0, "Price must be greater than 0");
+        let owner_id = env::predecessor_account_id();
+
+        if let Some(expires_at) = expires_at {
+            assert!(expires_at.0 > env::block_timestamp(), "Bundle must expire in the future");
+        }
+
+        //every collection in the bundle must still be whitelisted, and the share of the price each token is sold for
+        //must be at least the min price of its collection
+        for (item, item_price) in items.iter().zip(split_price(price.0, items.len())) {
+            if let Err(error) = self.internal_check_listing(&item.nft_contract_id, U128(item_price), &Currency::Near) {
+                env::panic_str(&error);
+            }
+        }
+
+        //take down the listing of every item. The bundle reuses the storage those sales were paid for
+        let mut bundle_items: Vec<BundleItem> = Vec::with_capacity(items.len());
+        for BundleItemArgs { nft_contract_id, token_id } in items {
+            let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+            let sale = self.sales.get(&contract_and_token_id).expect("No sale");
+            assert_eq!(sale.owner_id, owner_id, "Must be sale owner");
+            assert!(matches!(sale.sale_type, SaleType::FixedPrice), "Auctions can't be bundled");
+            assert!(!sale.is_expired(), "The sale has expired");
+            assert!(!sale.custodial, "Tokens held by the market can't be bundled");
+
+            self.internal_remove_sale(nft_contract_id.clone(), token_id.clone());
+            bundle_items.push(BundleItem {
+                nft_contract_id,
+                token_id,
+                approval_id: sale.approval_id,
+            });
+        }
+
+        let bundle_id = self.next_bundle_id;
+        self.next_bundle_id += 1;
+
+        self.internal_add_bundle(&Bundle {
+            bundle_id: U64(bundle_id),
+            owner_id,
+            items: bundle_items,
+            price,
+            expires_at,
+        });
+
+        U64(bundle_id)
+    }
+
+    //removes a bundle from the market. The tokens stay approved but aren't listed anymore
+    #[payable]
+    pub fn unlist_bundle(&mut self, bundle_id: U64) {
+        //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
+        assert_one_yocto();
+        let bundle = self.internal_remove_bundle(bundle_id.0).expect("No bundle");
+        //if this fails, the remove bundle will revert
+        assert_eq!(env::predecessor_account_id(), bundle.owner_id, "Must be bundle owner");
+    }
+
+    //buys every token of a bundle with the attached deposit. Anything above the price is refunded.
+    //the tokens are first moved to the market and only handed to the buyer once all of them made it
+    #[payable]
+    pub fn buy_bundle(&mut self, bundle_id: U64) -> Promise {
+        let deposit = env::attached_deposit();
+        let bundle = self.internal_remove_bundle(bundle_id.0).expect("No bundle");
+
+        //get the buyer ID which is the person who called the function and make sure they're not the owner of the bundle
+        let buyer_id = env::predecessor_account_id();
+        assert_ne!(bundle.owner_id, buyer_id, "Cannot buy your own bundle.");
+        assert!(!bundle.is_expired(), "The bundle has expired");
+        //every transfer and the callback get their GAS up front, so make sure it's all there before taking the deposit
+        let required_gas = Self::bundle_purchase_gas(bundle.items.len());
+        require!(
+            env::prepaid_gas() >= required_gas,
+            format!("Attach at least {} TGas to buy this bundle", required_gas.0 / 1_000_000_000_000)
+        );
+        for item in &bundle.items {
+            self.assert_whitelisted(&item.nft_contract_id);
+        }
+        assert!(deposit >= bundle.price.0, "Attached deposit must be greater than or equal to the current price: {:?}", bundle.price.0);
+        if deposit > bundle.price.0 {
+            Promise::new(buyer_id.clone()).transfer(deposit - bundle.price.0);
+        }
+
+        //the fee of every item is taken from its share of the price. They're passed on to the callback so it
+        //checks the payouts against the same amounts
+        let item_prices = bundle.item_prices();
+        let item_fees: Vec<U128> = bundle
+            .items
+            .iter()
+            .zip(&item_prices)
+            .map(|(item, item_price)| U128(self.internal_fee_amount(&bundle.owner_id, &item.nft_contract_id, *item_price)))
+            .collect();
+
+        //transfer every token to the market at once, each getting the payout for its share of the price minus its fee
+        let transfers = bundle
+            .items
+            .iter()
+            .zip(item_prices.iter().zip(&item_fees))
+            .map(|(item, (item_price, item_fee))| {
+                ext_contract::ext(item.nft_contract_id.clone())
+                    .with_attached_deposit(1)
+                    .with_static_gas(GAS_FOR_NFT_TRANSFER)
+                    .nft_transfer_payout(
+                        env::current_account_id(),
+                        item.token_id.clone(),
+                        item.approval_id,
+                        "payout from market".to_string(),
+                        U128(item_price - item_fee.0),
+                        10,
+                    )
+            })
+            .reduce(|transfers, transfer| transfers.and(transfer))
+            .unwrap();
+
+        //the callback moves every token on to the buyer (or back to the owner), so it needs GAS for each of them
+        let resolve_gas = Self::resolve_bundle_purchase_gas(bundle.items.len());
+        transfers.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(resolve_gas)
+                .resolve_bundle_purchase(buyer_id, bundle, item_fees),
+        )
+    }
+
+    /*
+    private method used to resolve the joint nft_transfer_payout calls of a bundle. If every token was transferred
+    with an authentic payout, the tokens are handed to the buyer and the accounts are paid. Otherwise the tokens that
+    did reach the market are sent back to the owner and the buyer is refunded. Returns the price paid out.
+    */
+    #[private]
+    pub fn resolve_bundle_purchase(&mut self, buyer_id: AccountId, bundle: Bundle, item_fees: Vec<U128>) -> U128 {
+        //every item is settled like a purchase of its own, for its share of the price
+        let purchases: Vec<Purchase> = bundle
+            .items
+            .iter()
+            .zip(bundle.item_prices())
+            .zip(&item_fees)
+            .map(|((item, item_price), item_fee)| Purchase {
+                nft_contract_id: item.nft_contract_id.clone(),
+                token_id: item.token_id.clone(),
+                seller_id: bundle.owner_id.clone(),
+                buyer_id: buyer_id.clone(),
+                price: U128(item_price),
+                fee: *item_fee,
+                deposit: U128(item_price),
+                custodial: false,
+            })
+            .collect();
+        let payouts: Vec<Option<HashMap<AccountId, U128>>> = purchases
+            .iter()
+            .enumerate()
+            .map(|(index, purchase)| verified_payout_at(index as u64, purchase.net_price()))
+            .collect();
+
+        //the bundle only settles if every transfer went through
+        let settled = payouts.iter().all(Option::is_some);
+        let receiver_id = if settled { buyer_id.clone() } else { bundle.owner_id.clone() };
+
+        //hand over every token the market received. Those that don't make it can be claimed by the receiver
+        let received: Vec<BundleItem> = bundle
+            .items
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| matches!(env::promise_result(*index as u64), PromiseResult::Successful(_)))
+            .map(|(_, item)| item)
+            .collect();
+        if let Some(handovers) = received
+            .iter()
+            .map(|item| internal_handover(item, &receiver_id))
+            .reduce(|handovers, handover| handovers.and(handover))
+        {
+            handovers.then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_HANDOVER)
+                    .resolve_handover(receiver_id.clone(), received),
+            );
+        }
+
+        if !settled {
+            Promise::new(buyer_id).transfer(bundle.price.0);
+            for purchase in &purchases {
+                self.internal_emit_purchase(purchase, Currency::Near, purchase.deposit.0);
+            }
+            return U128(0);
+        }
+
+        // NEAR payouts. The fees of all items are sent at once
+        for payout in payouts.into_iter().flatten() {
+            self.internal_distribute_near(payout, 0);
+        }
+        let total_fee: Balance = item_fees.iter().map(|item_fee| item_fee.0).sum();
+        if total_fee > 0 {
+            Promise::new(self.fee_recipient.clone()).transfer(total_fee);
+        }
+        //every card of the bundle counts towards the seller's fee tier for its share of the price
+        for purchase in &purchases {
+            self.internal_record_seller_volume(purchase);
+            self.internal_record_trade(purchase, &Currency::Near);
+            self.internal_emit_purchase(purchase, Currency::Near, 0);
+        }
+
+        //return the price payout out
+        bundle.price
+    }
+
+    /*
+    private method used to resolve the joint nft_transfer calls handing the tokens of a bundle over. A token the market
+    couldn't transfer stays with the market until its receiver claims it with `claim_token`
+    */
+    #[private]
+    pub fn resolve_handover(&mut self, receiver_id: AccountId, items: Vec<BundleItem>) {
+        for (index, item) in items.into_iter().enumerate() {
+            if let PromiseResult::Successful(_) = env::promise_result(index as u64) {
+                continue;
+            }
+            let contract_and_token_id = format!("{}{}{}", item.nft_contract_id, DELIMETER, item.token_id);
+            env::log_str(&format!("{} couldn't be handed over to {}, it can be claimed", contract_and_token_id, receiver_id));
+            self.pending_handovers.insert(&contract_and_token_id, &receiver_id);
+        }
+    }
+
+    //retries handing over a token of a bundle the market still holds. Only the account it is owed to can claim it
+    #[payable]
+    pub fn claim_token(&mut self, nft_contract_id: AccountId, token_id: TokenId) -> Promise {
+        //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
+        assert_one_yocto();
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        let receiver_id = self.pending_handovers.remove(&contract_and_token_id).expect("No token to claim");
+        assert_eq!(env::predecessor_account_id(), receiver_id, "Only {} can claim this token", receiver_id);
+
+        //the market owns the token, so the approval ID isn't used
+        let item = BundleItem { nft_contract_id, token_id, approval_id: 0 };
+        internal_handover(&item, &receiver_id).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_HANDOVER)
+                .resolve_handover(receiver_id, vec![item]),
+        )
+    }
+
+    //removes up to `limit` expired bundles starting at `from_index`, like prune_expired does for sales. Can be called by
+    //anyone. Returns how many bundles were removed and the index the next call should start from
+    pub fn prune_expired_bundles(&mut self, from_index: Option<U64>, limit: u64) -> PruneResult {
+        let mut index = from_index.unwrap_or(U64(0)).0;
+        let mut pruned = 0;
+
+        for _ in 0..limit {
+            let bundle = match self.bundles.values_as_vector().get(index) {
+                Some(bundle) => bundle,
+                None => break,
+            };
+            //removing a bundle moves the last one into its slot, so the same index is read again instead of moving on
+            if bundle.is_expired() {
+                self.internal_remove_bundle(bundle.bundle_id.0);
+                pruned += 1;
+            } else {
+                index += 1;
+            }
+        }
+
+        PruneResult {
+            pruned: U64(pruned),
+            next_index: U64(index),
+        }
+    }
+
+    //views
+
+    //get a bundle by its ID, unless it has expired
+    pub fn get_bundle(&self, bundle_id: U64) -> Option<Bundle> {
+        self.bundles.get(&bundle_id.0).filter(|bundle| !bundle.is_expired())
+    }
+
+    //account a token of a bundle the market couldn't hand over can be claimed by, if there is one
+    pub fn get_pending_handover(&self, contract_and_token_id: ContractAndTokenId) -> Option<AccountId> {
+        self.pending_handovers.get(&contract_and_token_id)
+    }
+
+    //returns paginated bundles on the market
+    pub fn get_bundles(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<Bundle> {
+        //where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        self.bundles
+            .values()
+            .skip(start as usize)
+            .take(limit.unwrap_or(0) as usize)
+            //expired bundles can't be bought anymore so they're left out
+            .filter(|bundle| !bundle.is_expired())
+            .collect()
+    }
+
+    //returns the number of bundles for a given account
+    pub fn get_supply_bundles_by_owner_id(&self, account_id: AccountId) -> U64 {
+        U64(self.bundles_by_owner_id.get(&account_id).map(|bundle_ids| bundle_ids.len()).unwrap_or(0))
+    }
+
+    //returns paginated bundles for a given account
+    pub fn get_bundles_by_owner_id(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Bundle> {
+        let bundle_ids = if let Some(bundle_ids) = self.bundles_by_owner_id.get(&account_id) {
+            bundle_ids
+        } else {
+            return vec![];
+        };
+
+        //where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        bundle_ids
+            .as_vector()
+            .iter()
+            .skip(start as usize)
+            .take(limit.unwrap_or(0) as usize)
+            .map(|bundle_id| self.bundles.get(&bundle_id).unwrap())
+            .filter(|bundle| !bundle.is_expired())
+            .collect()
+    }
+}
+
+//transfers a token the market holds to the account it is meant for
+fn internal_handover(item: &BundleItem, receiver_id: &AccountId) -> Promise {
+    ext_nft_contract::ext(item.nft_contract_id.clone())
+        .with_attached_deposit(1)
+        .with_static_gas(GAS_FOR_NFT_TRANSFER)
+        .nft_transfer(receiver_id.clone(), item.token_id.clone(), None, Some("bundle from market".to_string()))
+}
+
+impl Contract {
+    //GAS resolve_bundle_purchase needs to settle a bundle and hand over its tokens
+    fn resolve_bundle_purchase_gas(item_count: usize) -> Gas {
+        Gas(GAS_FOR_RESOLVE_BUNDLE_PURCHASE.0 + GAS_FOR_NFT_TRANSFER.0 * item_count as u64 + GAS_FOR_RESOLVE_HANDOVER.0)
+    }
+
+    //GAS buy_bundle needs: a transfer to the market per token, the callback and the call itself. 225 TGas for 5 tokens
+    pub(crate) fn bundle_purchase_gas(item_count: usize) -> Gas {
+        Gas(GAS_FOR_BUY_BUNDLE.0 + GAS_FOR_NFT_TRANSFER.0 * item_count as u64 + Self::resolve_bundle_purchase_gas(item_count).0)
+    }
+
+    //adds a bundle to the bundle indexes
+    pub(crate) fn internal_add_bundle(&mut self, bundle: &Bundle) {
+        self.bundles.insert(&bundle.bundle_id.0, bundle);
+
+        //get the bundle IDs of the owner. If there are none, we create a new empty set
+        let mut by_owner_id = self.bundles_by_owner_id.get(&bundle.owner_id).unwrap_or_else(|| {
+            UnorderedSet::new(
+                StorageKey::BundlesByOwnerIdInner {
+                    //we get a new unique prefix for the collection by hashing the owner
+                    account_id_hash: hash_account_id(&bundle.owner_id),
+                }
+                .try_to_vec()
+                .unwrap(),
+            )
+        });
+        by_owner_id.insert(&bundle.bundle_id.0);
+        self.bundles_by_owner_id.insert(&bundle.owner_id, &by_owner_id);
+
+        //get the bundle IDs of every nft contract in the bundle. If there are none, we create a new empty set
+        for item in &bundle.items {
+            let mut by_nft_contract_id = self.bundles_by_nft_contract_id.get(&item.nft_contract_id).unwrap_or_else(|| {
+                UnorderedSet::new(
+                    StorageKey::BundlesByNFTContractIdInner {
+                        //we get a new unique prefix for the collection by hashing the nft contract
+                        account_id_hash: hash_account_id(&item.nft_contract_id),
+                    }
+                    .try_to_vec()
+                    .unwrap(),
+                )
+            });
+            by_nft_contract_id.insert(&bundle.bundle_id.0);
+            self.bundles_by_nft_contract_id.insert(&item.nft_contract_id, &by_nft_contract_id);
+        }
+    }
+
+    //removes a bundle from the bundle indexes and returns it, if there was one
+    pub(crate) fn internal_remove_bundle(&mut self, bundle_id: BundleId) -> Option<Bundle> {
+        let bundle = self.bundles.remove(&bundle_id)?;
+
+        let mut by_owner_id = self.bundles_by_owner_id.get(&bundle.owner_id).expect("No bundle by_owner_id");
+        by_owner_id.remove(&bundle_id);
+        if by_owner_id.is_empty() {
+            self.bundles_by_owner_id.remove(&bundle.owner_id);
+        } else {
+            self.bundles_by_owner_id.insert(&bundle.owner_id, &by_owner_id);
+        }
+
+        //items of the same nft contract share its set, which is already gone once the first of them emptied it
+        for item in &bundle.items {
+            let mut by_nft_contract_id = match self.bundles_by_nft_contract_id.get(&item.nft_contract_id) {
+                Some(by_nft_contract_id) => by_nft_contract_id,
+                None => continue,
+            };
+            by_nft_contract_id.remove(&bundle_id);
+            if by_nft_contract_id.is_empty() {
+                self.bundles_by_nft_contract_id.remove(&item.nft_contract_id);
+            } else {
+                self.bundles_by_nft_contract_id.insert(&item.nft_contract_id, &by_nft_contract_id);
+            }
+        }
+
+        Some(bundle)
+    }
+}
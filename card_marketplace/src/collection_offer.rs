@@ -0,0 +1,346 @@
+use crate::*;
+
+//unique ID of a collection offer
+pub type CollectionOfferId = u64;
+
+//collection offers on a collection, sorted by amount then offer ID
+pub type CollectionOfferBook = TreeMap<(Balance, CollectionOfferId), ()>;
+
+//a bid on any token of a collection. `amount * quantity` is escrowed by the market and every fill buys one token for `amount`
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionOffer {
+    pub offer_id: U64,
+    //account that made the offer and receives the tokens
+    pub bidder_id: AccountId,
+    //collection the offer is for
+    pub nft_contract_id: AccountId,
+    //amount of NEAR offered per token
+    pub amount: U128,
+    //how many tokens are still wanted
+    pub quantity: u32,
+    //the offer can't be filled anymore after this time (nanoseconds)
+    pub expires_at: Option<U64>,
+}
+
+impl CollectionOffer {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| env::block_timestamp() >= expires_at.0)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    //bids `amount` per token on `quantity` tokens of a whitelisted collection. The attached deposit has to be
+    //`amount * quantity` and is held by the market. Returns the ID holders fill the offer with
+    #[payable]
+    pub fn make_collection_offer(
+        &mut self,
+        nft_contract_id: AccountId,
+        amount: U128,
+        quantity: u32,
+        expires_at: Option<U64>,
+    ) -> U64 {
+        let bidder_id = env::predecessor_account_id();
+        assert!(self.whitelist.get(&nft_contract_id).is_some(), "No Whitelist");
+        assert!(amount.0 > 0, "Offer amount must be greater than 0");
+        assert!(quantity > 0, "Quantity must be greater than 0");
+        assert_eq!(
+            env::attached_deposit(),
+            amount.0 * quantity as u128,
+            "Attached deposit must be equal to amount * quantity"
+        );
+        if let Some(expires_at) = expires_at {
+            assert!(expires_at.0 > env::block_timestamp(), "Offer must expire in the future");
+        }
+
+        //collection offers take up storage just like sales, so the bidder needs enough storage for 1 EXTRA entry
+        let bidder_paid_storage = self.storage_deposits.get(&bidder_id).unwrap_or(0);
+        let bidder_storage_required = self.internal_storage_entries(&bidder_id) as u128 * STORAGE_PER_SALE + STORAGE_PER_SALE;
+        assert!(
+            bidder_paid_storage >= bidder_storage_required,
+            "Insufficient storage paid: {}, for {} sales and offers at {} rate of per sale",
+            bidder_paid_storage, bidder_storage_required / STORAGE_PER_SALE, STORAGE_PER_SALE
+        );
+
+        let offer_id = self.next_collection_offer_id;
+        self.next_collection_offer_id += 1;
+
+        self.internal_add_collection_offer(&CollectionOffer {
+            offer_id: U64(offer_id),
+            bidder_id,
+            nft_contract_id,
+            amount,
+            quantity,
+            expires_at,
+        });
+
+        U64(offer_id)
+    }
+
+    //withdraws a collection offer and refunds what is left of the escrow. Anyone can clear an offer once it has expired
+    #[payable]
+    pub fn cancel_collection_offer(&mut self, offer_id: U64) {
+        //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
+        assert_one_yocto();
+        let offer = self.internal_remove_collection_offer(offer_id.0).expect("No collection offer");
+        assert!(
+            env::predecessor_account_id() == offer.bidder_id || offer.is_expired(),
+            "Only the bidder can cancel an offer before it expires"
+        );
+
+        Promise::new(offer.bidder_id).transfer(offer.amount.0 * offer.quantity as u128);
+    }
+
+    /*
+    private method used to resolve the nft_transfer_payout of a collection offer fill. A fill that went through is settled
+    like any accepted offer. If it failed, the unit taken off the offer is put back on it and its NEAR stays escrowed.
+    If the offer is gone by then (its last unit was being filled, or it was cancelled or purged), the unit is refunded
+    */
+    #[private]
+    pub fn resolve_collection_offer_fill(&mut self, purchase: Purchase, offer_id: U64) -> PurchaseResult {
+        if verified_payout(purchase.net_price()).is_some() {
+            return self.resolve_purchase(purchase);
+        }
+        let mut offer = match self.collection_offers.get(&offer_id.0) {
+            Some(offer) => offer,
+            None => return self.resolve_purchase(purchase),
+        };
+
+        offer.quantity += 1;
+        self.collection_offers.insert(&offer_id.0, &offer);
+        env::log_str(&format!("The fill of collection offer {} failed, the offer wants {} tokens again", offer_id.0, offer.quantity));
+        PurchaseResult {
+            charged: U128(0),
+            refunded: U128(0),
+        }
+    }
+
+    //views
+
+    //get a collection offer by its ID
+    pub fn get_collection_offer(&self, offer_id: U64) -> Option<CollectionOffer> {
+        self.collection_offers.get(&offer_id.0)
+    }
+
+    //returns paginated collection offers on a given nft contract, expired ones included
+    pub fn get_collection_offers(
+        &self,
+        nft_contract_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<CollectionOffer> {
+        let offer_ids = if let Some(offer_ids) = self.collection_offers_by_nft_contract_id.get(&nft_contract_id) {
+            offer_ids
+        } else {
+            return vec![];
+        };
+
+        //where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        offer_ids
+            .as_vector()
+            .iter()
+            .skip(start as usize)
+            .take(limit.unwrap_or(0) as usize)
+            .map(|offer_id| self.collection_offers.get(&offer_id).unwrap())
+            .collect()
+    }
+
+    //get the highest collection offer that can still be filled for a page of the whitelisted nft contracts. Contracts
+    //without one are left out of the page
+    pub fn get_best_collection_offers(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<(AccountId, CollectionOffer)> {
+        //where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        self.whitelist
+            .keys()
+            .skip(start as usize)
+            .take(limit.unwrap_or(0) as usize)
+            .filter_map(|nft_contract_id| {
+                self.get_best_collection_offer(nft_contract_id.clone())
+                    .map(|offer| (nft_contract_id, offer))
+            })
+            .collect()
+    }
+
+    //get the highest collection offer that can still be filled for a given nft contract
+    pub fn get_best_collection_offer(&self, nft_contract_id: AccountId) -> Option<CollectionOffer> {
+        let offer_book = self.collection_offers_by_amount.get(&nft_contract_id)?;
+
+        //walk down from the highest amount, only reading the offers that turn out to be expired on the way
+        std::iter::successors(offer_book.max(), |key| offer_book.lower(key))
+            .map(|(_, offer_id)| self.collection_offers.get(&offer_id).expect("No collection offer"))
+            .find(|offer| !offer.is_expired())
+    }
+
+    //returns the number of collection offers a bidder has up
+    pub fn get_supply_collection_offers_by_bidder_id(&self, account_id: AccountId) -> U64 {
+        U64(self
+            .collection_offers_by_bidder_id
+            .get(&account_id)
+            .map(|offer_ids| offer_ids.len())
+            .unwrap_or(0))
+    }
+
+    //returns paginated collection offers made by a given bidder
+    pub fn get_collection_offers_by_bidder_id(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<CollectionOffer> {
+        let offer_ids = if let Some(offer_ids) = self.collection_offers_by_bidder_id.get(&account_id) {
+            offer_ids
+        } else {
+            return vec![];
+        };
+
+        //where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        offer_ids
+            .as_vector()
+            .iter()
+            .skip(start as usize)
+            .take(limit.unwrap_or(0) as usize)
+            .map(|offer_id| self.collection_offers.get(&offer_id).unwrap())
+            .collect()
+    }
+}
+
+impl Contract {
+    //sells one token to a collection offer. Called from nft_on_approve once the holder approved the market
+    pub(crate) fn internal_fill_collection_offer(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        owner_id: AccountId,
+        approval_id: u64,
+        offer_id: CollectionOfferId,
+    ) -> Promise {
+        let mut offer = self.collection_offers.get(&offer_id).expect("No collection offer");
+        assert_eq!(offer.nft_contract_id, nft_contract_id, "The offer is for {}", offer.nft_contract_id);
+        assert!(!offer.is_expired(), "The offer has expired");
+        assert_ne!(owner_id, offer.bidder_id, "Cannot fill your own offer.");
+        self.assert_whitelisted(&nft_contract_id);
+
+        //take one unit off the offer, removing it once it is filled
+        offer.quantity -= 1;
+        if offer.quantity == 0 {
+            self.internal_remove_collection_offer(offer_id);
+        } else {
+            self.collection_offers.insert(&offer_id, &offer);
+        }
+
+        let purchase = self.internal_sell_approved_token(nft_contract_id.clone(), token_id.clone(), owner_id, offer.bidder_id, offer.amount);
+        internal_transfer_payout(&purchase, approval_id).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_COLLECTION_OFFER_FILL)
+                .resolve_collection_offer_fill(purchase, U64(offer_id)),
+        )
+    }
+
+    //adds a collection offer to the collection offer indexes
+    pub(crate) fn internal_add_collection_offer(&mut self, offer: &CollectionOffer) {
+        self.collection_offers.insert(&offer.offer_id.0, offer);
+
+        //get the offer IDs for the nft contract. If there are none, we create a new empty set
+        let mut by_nft_contract_id = self
+            .collection_offers_by_nft_contract_id
+            .get(&offer.nft_contract_id)
+            .unwrap_or_else(|| {
+                UnorderedSet::new(
+                    StorageKey::CollectionOffersByNFTContractIdInner {
+                        //we get a new unique prefix for the collection by hashing the nft contract
+                        account_id_hash: hash_account_id(&offer.nft_contract_id),
+                    }
+                    .try_to_vec()
+                    .unwrap(),
+                )
+            });
+        by_nft_contract_id.insert(&offer.offer_id.0);
+        self.collection_offers_by_nft_contract_id
+            .insert(&offer.nft_contract_id, &by_nft_contract_id);
+
+        //get the offer IDs of the bidder. If there are none, we create a new empty set
+        let mut by_bidder_id = self
+            .collection_offers_by_bidder_id
+            .get(&offer.bidder_id)
+            .unwrap_or_else(|| {
+                UnorderedSet::new(
+                    StorageKey::CollectionOffersByBidderIdInner {
+                        //we get a new unique prefix for the collection by hashing the bidder
+                        account_id_hash: hash_account_id(&offer.bidder_id),
+                    }
+                    .try_to_vec()
+                    .unwrap(),
+                )
+            });
+        by_bidder_id.insert(&offer.offer_id.0);
+        self.collection_offers_by_bidder_id.insert(&offer.bidder_id, &by_bidder_id);
+
+        //get the offer book of the nft contract. If there is none, we create a new empty one
+        let mut by_amount = self
+            .collection_offers_by_amount
+            .get(&offer.nft_contract_id)
+            .unwrap_or_else(|| {
+                TreeMap::new(
+                    StorageKey::CollectionOffersByAmountInner {
+                        //we get a new unique prefix for the collection by hashing the nft contract
+                        account_id_hash: hash_account_id(&offer.nft_contract_id),
+                    }
+                    .try_to_vec()
+                    .unwrap(),
+                )
+            });
+        by_amount.insert(&(offer.amount.0, offer.offer_id.0), &());
+        self.collection_offers_by_amount.insert(&offer.nft_contract_id, &by_amount);
+    }
+
+    //removes a collection offer from the collection offer indexes and returns it, if there was one
+    pub(crate) fn internal_remove_collection_offer(&mut self, offer_id: CollectionOfferId) -> Option<CollectionOffer> {
+        let offer = self.collection_offers.remove(&offer_id)?;
+
+        let mut by_nft_contract_id = self
+            .collection_offers_by_nft_contract_id
+            .get(&offer.nft_contract_id)
+            .expect("No collection offer by nft_contract_id");
+        by_nft_contract_id.remove(&offer_id);
+        if by_nft_contract_id.is_empty() {
+            self.collection_offers_by_nft_contract_id.remove(&offer.nft_contract_id);
+        } else {
+            self.collection_offers_by_nft_contract_id
+                .insert(&offer.nft_contract_id, &by_nft_contract_id);
+        }
+
+        let mut by_bidder_id = self
+            .collection_offers_by_bidder_id
+            .get(&offer.bidder_id)
+            .expect("No collection offer by bidder_id");
+        by_bidder_id.remove(&offer_id);
+        if by_bidder_id.is_empty() {
+            self.collection_offers_by_bidder_id.remove(&offer.bidder_id);
+        } else {
+            self.collection_offers_by_bidder_id.insert(&offer.bidder_id, &by_bidder_id);
+        }
+
+        let mut by_amount = self
+            .collection_offers_by_amount
+            .get(&offer.nft_contract_id)
+            .expect("No collection offer by amount");
+        by_amount.remove(&(offer.amount.0, offer_id));
+        if by_amount.is_empty() {
+            self.collection_offers_by_amount.remove(&offer.nft_contract_id);
+        } else {
+            self.collection_offers_by_amount.insert(&offer.nft_contract_id, &by_amount);
+        }
+
+        Some(offer)
+    }
+}
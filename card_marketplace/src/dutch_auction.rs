@@ -0,0 +1,73 @@
+use crate::*;
+
+//declining price sale. The price goes from the sale's price (start price) down to the floor price between the two timestamps
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DutchAuction {
+    //lowest price the sale goes down to
+    pub floor_price: U128,
+    //when the price starts declining (nanoseconds)
+    pub start_at: U64,
+    //when the price reaches the floor price (nanoseconds)
+    pub end_at: U64,
+    //if set, the price drops every `step_interval` nanoseconds instead of continuously
+    pub step_interval: Option<U64>,
+}
+
+//dutch auction parameters passed in the SaleArgs msg when listing through nft_approve
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DutchAuctionArgs {
+    pub floor_price: U128,
+    //defaults to now
+    pub start_at: Option<U64>,
+    pub end_at: U64,
+    pub step_interval: Option<U64>,
+}
+
+impl DutchAuctionArgs {
+    //validates the parameters against the start price of the sale
+    pub fn into_dutch_auction(self, start_price: U128) -> DutchAuction {
+        let start_at = self.start_at.map(|start_at| start_at.0).unwrap_or_else(env::block_timestamp);
+        assert!(self.end_at.0 > start_at, "Dutch auction must end after it starts");
+        assert!(
+            self.floor_price.0 <= start_price.0,
+            "Floor price must be lower than or equal to the start price"
+        );
+        if let Some(step_interval) = self.step_interval {
+            assert!(step_interval.0 > 0, "Step interval must be greater than 0");
+        }
+
+        DutchAuction {
+            floor_price: self.floor_price,
+            start_at: U64(start_at),
+            end_at: self.end_at,
+            step_interval: self.step_interval,
+        }
+    }
+}
+
+impl DutchAuction {
+    //price of the sale at the given timestamp
+    pub fn price_at(&self, start_price: Balance, timestamp: u64) -> Balance {
+        if timestamp <= self.start_at.0 {
+            return start_price;
+        }
+        if timestamp >= self.end_at.0 {
+            return self.floor_price.0;
+        }
+
+        //round the elapsed time down to the last step when the price declines stepwise
+        let mut elapsed = timestamp - self.start_at.0;
+        if let Some(step_interval) = self.step_interval {
+            elapsed -= elapsed % step_interval.0;
+        }
+        let (elapsed, duration) = (elapsed as u128, (self.end_at.0 - self.start_at.0) as u128);
+
+        //yocto prices times nanosecond durations overflow u128, so the difference is split into whole durations and
+        //the remainder, which are scaled separately
+        let diff = start_price - self.floor_price.0;
+        let decline = (diff / duration) * elapsed + (diff % duration) * elapsed / duration;
+        start_price - decline
+    }
+}
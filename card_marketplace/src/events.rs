@@ -0,0 +1,173 @@
+use crate::*;
+use near_sdk::serde_json;
+
+/// NEP-297 events logged by the marketplace
+
+//every event is logged as `EVENT_JSON:{"standard": "tenamint_market", "version": ..., "event": ..., "data": [...]}`
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+pub enum MarketEvent {
+    //the owner proposed a new owner, and the new owner accepted
+    OwnershipProposal([OwnershipUpdate; 1]),
+    OwnershipTransfer([OwnershipUpdate; 1]),
+    RoleGrant([RoleUpdate; 1]),
+    RoleRevoke([RoleUpdate; 1]),
+    FeeUpdate([FeeUpdate; 1]),
+    FeeRecipientUpdate([FeeRecipientUpdate; 1]),
+    FeeTiersUpdate([FeeTiersUpdate; 1]),
+    //a TokenizedCard was allowlisted (or its min price changed) / disallowed
+    WhitelistAdd([WhitelistUpdate; 1]),
+    WhitelistRemove([WhitelistUpdate; 1]),
+    //a sale was listed / changed (price, currency or a new bid) / taken off the market, whether it was sold or not
+    ListingCreate([ListingLog; 1]),
+    ListingUpdate([ListingLog; 1]),
+    ListingRemove([ListingLog; 1]),
+    //a purchase went through / failed and the buyer got their payment back
+    PurchaseSettle([PurchaseLog; 1]),
+    PurchaseRefund([PurchaseLog; 1]),
+    //the fee taken from a purchase that went through
+    FeePaid([FeeLog; 1]),
+    //a fungible token was accepted / removed as currency
+    CurrencyAdd([CurrencyUpdate; 1]),
+    CurrencyRemove([CurrencyUpdate; 1]),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ListingLog {
+    pub nft_contract_id: String,
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub approval_id: u64,
+    pub price: U128,
+    pub currency: Currency,
+    pub sale_type: SaleType,
+    pub expires_at: Option<U64>,
+    pub custodial: bool,
+}
+
+impl From<&Sale> for ListingLog {
+    fn from(sale: &Sale) -> Self {
+        ListingLog {
+            nft_contract_id: sale.nft_contract_id.clone(),
+            token_id: sale.token_id.clone(),
+            owner_id: sale.owner_id.clone(),
+            approval_id: sale.approval_id,
+            price: sale.price,
+            currency: sale.currency.clone(),
+            sale_type: sale.sale_type.clone(),
+            expires_at: sale.expires_at,
+            custodial: sale.custodial,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeLog {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub seller_id: AccountId,
+    pub fee_recipient: AccountId,
+    pub currency: Currency,
+    pub amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseLog {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub seller_id: AccountId,
+    pub buyer_id: AccountId,
+    pub currency: Currency,
+    pub price: U128,
+    pub fee: U128,
+    //what the buyer ended up paying and what they got back
+    pub charged: U128,
+    pub refunded: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OwnershipUpdate {
+    pub old_owner_id: AccountId,
+    pub new_owner_id: AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoleUpdate {
+    pub account_id: AccountId,
+    pub role: Role,
+    //who granted or revoked the role
+    pub updated_by: AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeUpdate {
+    //in basis points
+    pub old_fee_bps: u16,
+    pub new_fee_bps: u16,
+    pub updated_by: AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeTiersUpdate {
+    pub fee_tiers: Vec<FeeTier>,
+    pub updated_by: AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeRecipientUpdate {
+    pub old_fee_recipient: AccountId,
+    pub new_fee_recipient: AccountId,
+    pub updated_by: AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WhitelistUpdate {
+    pub nft_contract_id: AccountId,
+    //not set when the card is disallowed
+    pub min_price_per_token: Option<U128>,
+    pub fee_bps: Option<u16>,
+    //not set when the card is disallowed
+    pub ft_min_prices: Option<HashMap<FungibleTokenId, U128>>,
+    pub updated_by: AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CurrencyUpdate {
+    pub ft_token_id: FungibleTokenId,
+    pub updated_by: AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a MarketEvent,
+}
+
+impl MarketEvent {
+    pub fn emit(&self) {
+        let log = EventLog {
+            standard: "tenamint_market",
+            version: "1.0.0",
+            event: self,
+        };
+        env::log_str(&format!(
+            "EVENT_JSON:{}",
+            serde_json::to_string(&log).unwrap_or_else(|_| env::abort())
+        ));
+    }
+}
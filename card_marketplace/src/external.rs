@@ -20,4 +20,30 @@ trait ExtContract {
         //the maximum amount of accounts the market can payout at once (this is limited by GAS)
 		max_len_payout: u32,
     );
+}
+
+//transfers a token the market holds itself back out, e.g. the items of a bundle that couldn't be sold
+#[ext_contract(ext_nft_contract)]
+trait ExtNftContract {
+    fn nft_transfer(
+        &mut self,
+        receiver_id: AccountId, //account receiving the token
+        token_id: TokenId, //token ID to transfer
+        approval_id: Option<u64>, //not needed since the market owns the token
+        memo: Option<String>, //memo (to include some context)
+    );
+
+    //used to check who owns a token and the approval ID the market was given for it
+    fn nft_token(&self, token_id: TokenId);
+}
+
+//cross contract call to the payment token contract, used to pay out sales that were paid in fungible tokens
+#[ext_contract(ext_ft_contract)]
+trait ExtFtContract {
+    fn ft_transfer(
+        &mut self,
+        receiver_id: AccountId, //account receiving the tokens
+        amount: U128, //amount of tokens to send
+        memo: Option<String>, //memo (to include some context)
+    );
 }
\ No newline at end of file
@@ -0,0 +1,64 @@
+use crate::*;
+
+//fees are in basis points: 10_000 is 100%
+pub const MAX_FEE_BPS: u16 = 10_000;
+
+//sellers who sold for at least `min_volume` NEAR on the market pay `fee_bps` if it's lower than the fee they'd pay otherwise
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeTier {
+    pub min_volume: U128,
+    pub fee_bps: u16,
+}
+
+#[near_bindgen]
+impl Contract {
+    //views
+
+    //fee in basis points a seller pays on a sale of the given nft contract. The collection's override replaces the
+    //default fee, and the seller's tier applies on top of that when it's lower
+    pub fn get_fee_for(&self, seller_id: AccountId, nft_contract_id: AccountId) -> u16 {
+        let base_fee_bps = self
+            .whitelist
+            .get(&nft_contract_id)
+            .and_then(|entry| entry.fee_bps)
+            .unwrap_or(self.fee_bps);
+
+        //pick the tier with the highest volume the seller has reached
+        let seller_volume = self.get_seller_volume(seller_id).0;
+        let tier_fee_bps = self
+            .fee_tiers
+            .iter()
+            .filter(|tier| seller_volume >= tier.min_volume.0)
+            .max_by_key(|tier| tier.min_volume.0)
+            .map(|tier| tier.fee_bps);
+
+        match tier_fee_bps {
+            Some(tier_fee_bps) => base_fee_bps.min(tier_fee_bps),
+            None => base_fee_bps,
+        }
+    }
+
+    //total price in NEAR of the whitelisted cards an account sold
+    pub fn get_seller_volume(&self, account_id: AccountId) -> U128 {
+        U128(self.volume_by_seller_id.get(&account_id).unwrap_or(0))
+    }
+}
+
+impl Contract {
+    //fee taken from the gross price of a sale, before the payout is distributed
+    pub(crate) fn internal_fee_amount(&self, seller_id: &AccountId, nft_contract_id: &AccountId, price: Balance) -> Balance {
+        price * self.get_fee_for(seller_id.clone(), nft_contract_id.clone()) as u128 / MAX_FEE_BPS as u128
+    }
+
+    //adds the price of a completed NEAR purchase to the seller's volume for the fee tiers. Only whitelisted cards
+    //count, a seller could reach the cheapest tier by trading a worthless card of their own otherwise. Fungible
+    //token amounts aren't in NEAR units, so those purchases don't count either
+    pub(crate) fn internal_record_seller_volume(&mut self, purchase: &Purchase) {
+        if self.whitelist.get(&purchase.nft_contract_id).is_none() {
+            return;
+        }
+        let volume = self.volume_by_seller_id.get(&purchase.seller_id).unwrap_or(0);
+        self.volume_by_seller_id.insert(&purchase.seller_id, &(volume + purchase.price.0));
+    }
+}
@@ -0,0 +1,97 @@
+use crate::*;
+
+/// transfer callbacks from FT Contracts
+
+//struct for the msg passed to ft_transfer_call when paying for a sale
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseArgs {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+}
+
+/*
+    trait that will be used as the callback from the FT contract. When ft_transfer_call is
+    called, the tokens are moved to the marketplace and this function is invoked. Whatever
+    amount it returns is refunded to the sender by the FT contract.
+    The payouts of a purchase are sent with ft_transfer without checking the result (see internal_ft_transfer),
+    so every account in the payout must be registered with the FT contract or its share stays with the market.
+*/
+pub(crate) trait FungibleTokenReceiver {
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128>;
+}
+
+//implementation of the trait
+#[near_bindgen]
+impl FungibleTokenReceiver for Contract {
+    /// where we buy (or bid on) the sale passed in the msg with the transferred tokens
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        // get the FT contract ID which is the predecessor and make sure the market accepts it
+        let ft_token_id = env::predecessor_account_id();
+        assert!(
+            self.approved_ft_token_ids.contains(&ft_token_id),
+            "{} is not accepted as payment",
+            ft_token_id
+        );
+
+        //the sale to buy comes from the msg field. If it isn't valid, the panic makes the FT contract refund the sender
+        let PurchaseArgs { nft_contract_id, token_id } =
+            near_sdk::serde_json::from_str(&msg).expect("Not valid PurchaseArgs");
+
+        //get the unique sale ID (contract + DELIMITER + token ID)
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+
+        //get the sale object from the unique sale ID. If the sale doesn't exist, panic.
+        let sale = self.sales.get(&contract_and_token_id).expect("No sale");
+
+        //the buyer is the account that transferred the tokens. Make sure they're not the owner of the sale
+        assert_ne!(sale.owner_id, sender_id, "Cannot bid on your own sale.");
+
+        //make sure the sale is listed in the token that was transferred
+        assert_eq!(
+            sale.currency,
+            Currency::FungibleToken(ft_token_id.clone()),
+            "This sale must be paid in {:?}",
+            sale.currency
+        );
+
+        //make sure the listing is still up
+        assert!(!sale.is_expired(), "The sale has expired");
+        self.assert_whitelisted(&nft_contract_id);
+
+        //for an auction the transferred tokens are the bid, which the market holds on to
+        if let SaleType::Auction(_) = sale.sale_type {
+            self.internal_place_bid(&contract_and_token_id, sale, sender_id, amount.0);
+            return PromiseOrValue::Value(U128(0));
+        }
+
+        //get the u128 price the token is currently sold for
+        let price = sale.current_price();
+
+        //make sure the transferred amount is greater than the price
+        assert!(amount.0 >= price, "Transferred amount must be greater than or equal to the current price: {:?}", price);
+
+        //process the purchase. The amount returned by resolve_ft_purchase is what the FT contract refunds to the buyer,
+        //which is anything transferred above the price
+        PromiseOrValue::Promise(self.process_purchase(
+            nft_contract_id,
+            token_id,
+            U128(price),
+            amount,
+            sender_id,
+            Some(ft_token_id),
+            false,
+            GAS_FOR_RESOLVE_PURCHASE,
+        ))
+    }
+}
@@ -1,4 +1,5 @@
 use crate::*;
+use near_sdk::promise_result_as_success;
 
 //used to generate a unique prefix in our storage collections (this is to avoid data collisions)
 pub(crate) fn hash_account_id(account_id: &AccountId) -> CryptoHash {
@@ -9,7 +10,142 @@ pub(crate) fn hash_account_id(account_id: &AccountId) -> CryptoHash {
     hash
 }
 
+//same as hash_account_id for collections that are prefixed by a unique sale ID
+pub(crate) fn hash_sale_id(contract_and_token_id: &ContractAndTokenId) -> CryptoHash {
+    let mut hash = CryptoHash::default();
+    hash.copy_from_slice(&env::sha256(contract_and_token_id.as_bytes()));
+    hash
+}
+
+//used to generate a unique prefix in our storage collections (this is to avoid data collisions)
+pub(crate) fn hash_order_book_id(order_book_id: &(AccountId, Currency)) -> CryptoHash {
+    let mut hash = CryptoHash::default();
+    hash.copy_from_slice(&env::sha256(&order_book_id.try_to_vec().unwrap()));
+    hash
+}
+
+//checks the payout object returned by nft_transfer_payout. Returns None if the transfer failed or the payout
+//doesn't add up to the price, in which case the buyer has to be refunded
+pub(crate) fn verified_payout(price: U128) -> Option<HashMap<AccountId, U128>> {
+    parse_payout(promise_result_as_success(), price)
+}
+
+//same as verified_payout for the result of a nft_transfer_payout call that was joined with others
+pub(crate) fn verified_payout_at(result_index: u64, price: U128) -> Option<HashMap<AccountId, U128>> {
+    match env::promise_result(result_index) {
+        PromiseResult::Successful(value) => parse_payout(Some(value), price),
+        _ => None,
+    }
+}
+
+//gives a token the market holds in custody back to its owner
+pub(crate) fn internal_return_custody(nft_contract_id: AccountId, token_id: TokenId, owner_id: AccountId) -> Promise {
+    ext_nft_contract::ext(nft_contract_id)
+        .with_attached_deposit(1)
+        .with_static_gas(GAS_FOR_NFT_TRANSFER)
+        .nft_transfer(owner_id, token_id, None, Some("returned from market custody".to_string()))
+}
+
+//reads the token returned by a nft_token call. Returns None if the call failed or the token doesn't exist
+pub(crate) fn token_result() -> Option<JsonToken> {
+    promise_result_as_success()
+        .and_then(|value| near_sdk::serde_json::from_slice::<Option<JsonToken>>(&value).ok())
+        .flatten()
+}
+
+fn parse_payout(value: Option<Vec<u8>>, price: U128) -> Option<HashMap<AccountId, U128>> {
+    value.and_then(|value| {
+        near_sdk::serde_json::from_slice::<Payout>(&value)
+            .ok()
+            .and_then(|payout_object| {
+                //we'll check if length of the payout object is > 10 or it's empty. In either case, we return None
+                if payout_object.payout.len() > 10 || payout_object.payout.is_empty() {
+                    env::log_str("Cannot have more than 10 royalties");
+                    None
+                } else {
+                    let mut remainder = price.0;
+                    for &value in payout_object.payout.values() {
+                        remainder = remainder.checked_sub(value.0)?;
+                    }
+                    if remainder == 0 || remainder == 1 {
+                        Some(payout_object.payout)
+                    } else {
+                        None
+                    }
+                }
+            })
+    })
+}
+
 impl Contract {
+    //makes sure sales can be listed in the given currency. NEAR is always accepted
+    pub(crate) fn assert_approved_currency(&self, currency: &Currency) {
+        if let Currency::FungibleToken(ft_token_id) = currency {
+            assert!(
+                self.approved_ft_token_ids.contains(ft_token_id),
+                "{} is not an accepted currency",
+                ft_token_id
+            );
+        }
+    }
+
+    //pays out a verified NEAR payout and sends the fee taken from the gross price to the fee recipient
+    pub(crate) fn internal_distribute_near(&self, payout: HashMap<AccountId, U128>, fee: Balance) {
+        if fee > 0 {
+            Promise::new(self.fee_recipient.clone()).transfer(fee);
+        }
+        for (receiver_id, amount) in payout {
+            Promise::new(receiver_id).transfer(amount.0);
+        }
+    }
+
+    //makes sure the collection is whitelisted. Every sale, bundle and offer of a collection stops trading as soon as
+    //it is disallowed, whether or not purge_collection went through them yet
+    pub(crate) fn assert_whitelisted(&self, nft_contract_id: &AccountId) {
+        if self.whitelist.get(nft_contract_id).is_some() {
+            return;
+        }
+        assert!(
+            !self.delisted_nft_contract_ids.contains(nft_contract_id),
+            "{} has been removed from the whitelist",
+            nft_contract_id
+        );
+        panic!("{} is not whitelisted", nft_contract_id);
+    }
+
+    //sends back funds the market was holding for an account, in the given currency
+    pub(crate) fn internal_refund(&self, currency: &Currency, receiver_id: AccountId, amount: Balance) {
+        match currency {
+            Currency::Near => {
+                if amount > 0 {
+                    Promise::new(receiver_id).transfer(amount);
+                }
+            }
+            Currency::FungibleToken(ft_token_id) => {
+                self.internal_ft_transfer(ft_token_id, receiver_id, amount)
+            }
+        }
+    }
+
+    //internal method for sending fungible tokens held by the market. Nothing is sent for a zero amount.
+    //the transfer is fire and forget: a receiver that isn't registered with the FT contract (NEP-145 storage_deposit)
+    //makes ft_transfer fail and the tokens stay with the market. A purchase pays up to 10 accounts plus the fee, which
+    //leaves no GAS for a callback on each transfer, so sellers and royalty receivers have to register beforehand
+    pub(crate) fn internal_ft_transfer(
+        &self,
+        ft_token_id: &FungibleTokenId,
+        receiver_id: AccountId,
+        amount: Balance,
+    ) {
+        if amount == 0 {
+            return;
+        }
+        ext_ft_contract::ext(ft_token_id.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(GAS_FOR_FT_TRANSFER)
+            .ft_transfer(receiver_id, U128(amount), Some("payout from market".to_string()));
+    }
+
     //internal method for removing a sale from the market. This returns the previously removed sale object
     pub(crate) fn internal_remove_sale(
         &mut self,
@@ -52,7 +188,51 @@ impl Contract {
                 .insert(&nft_contract_id, &by_nft_contract_id);
         }
 
+        self.internal_remove_from_order_book(&sale);
+
+        MarketEvent::ListingRemove([(&sale).into()]).emit();
+
         //return the sale object
         sale
     }
+
+    //removes a sale that won't be sold, sending back the bid escrowed on it if it's an auction and the token if the
+    //market holds it
+    pub(crate) fn internal_drop_sale(&mut self, nft_contract_id: AccountId, token_id: TokenId) -> Sale {
+        let sale = self.internal_remove_sale(nft_contract_id.clone(), token_id.clone());
+        if let SaleType::Auction(Auction { highest_bid: Some(bid), .. }) = &sale.sale_type {
+            self.internal_refund(&sale.currency, bid.bidder_id.clone(), bid.amount.0);
+        }
+        if sale.custodial {
+            internal_return_custody(nft_contract_id, token_id, sale.owner_id.clone());
+        }
+        sale
+    }
+
+    //the payout of a token the market held goes to the market as its owner: that share belongs to the seller. If the
+    //token couldn't be transferred, it is given back to the seller since its sale is gone
+    pub(crate) fn internal_settle_custody(
+        &self,
+        purchase: &Purchase,
+        payout: Option<HashMap<AccountId, U128>>,
+    ) -> Option<HashMap<AccountId, U128>> {
+        if !purchase.custodial {
+            return payout;
+        }
+
+        let payout = match payout {
+            Some(payout) => payout,
+            None => {
+                internal_return_custody(purchase.nft_contract_id.clone(), purchase.token_id.clone(), purchase.seller_id.clone());
+                return None;
+            }
+        };
+        let mut settled = HashMap::with_capacity(payout.len());
+        for (receiver_id, amount) in payout {
+            let receiver_id = if receiver_id == env::current_account_id() { purchase.seller_id.clone() } else { receiver_id };
+            let total = settled.get(&receiver_id).map_or(0, |total: &U128| total.0) + amount.0;
+            settled.insert(receiver_id, U128(total));
+        }
+        Some(settled)
+    }
 }
\ No newline at end of file
@@ -1,37 +1,82 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet};
+use near_sdk::collections::{LookupMap, LookupSet, TreeMap, UnorderedMap, UnorderedSet};
 use near_sdk::json_types::{U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
-    assert_one_yocto, env, ext_contract, near_bindgen, AccountId, Balance, Gas, PanicOnDefault,
-    Promise, CryptoHash, BorshStorageKey,
+    assert_one_yocto, env, ext_contract, near_bindgen, require, AccountId, Balance, Gas, PanicOnDefault,
+    Promise, PromiseOrValue, PromiseResult, CryptoHash, BorshStorageKey,
 };
 use std::collections::HashMap;
 
+use crate::admin::*;
+use crate::auction::*;
+use crate::bundle::*;
+use crate::collection_offer::*;
+use crate::dutch_auction::*;
+use crate::events::*;
 use crate::external::*;
+use crate::fee::*;
 use crate::internal::*;
+use crate::offer::*;
+use crate::order_book::*;
 use crate::sale::*;
+use crate::stats::*;
+use crate::whitelist::*;
+use crate::nft_callbacks::*;
 use near_sdk::env::STORAGE_PRICE_PER_BYTE;
 
+mod admin;
+mod auction;
+mod bundle;
+mod collection_offer;
+mod dutch_auction;
+mod events;
 mod external;
+mod fee;
+mod ft_callbacks;
 mod internal;
 mod nft_callbacks;
+mod offer;
+mod order_book;
 mod sale;
 mod sale_views;
+mod stats;
 mod whitelist;
 
 //GAS constants to attach to calls
 const GAS_FOR_RESOLVE_PURCHASE: Gas = Gas(115_000_000_000_000);
 const GAS_FOR_NFT_TRANSFER: Gas = Gas(15_000_000_000_000);
+const GAS_FOR_FT_TRANSFER: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_NFT_TOKEN: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_RESOLVE_LIST: Gas = Gas(30_000_000_000_000);
+const GAS_FOR_RESOLVE_VERIFY_SALE: Gas = Gas(30_000_000_000_000);
+//GAS buy_bundle uses itself, on top of the calls it makes
+const GAS_FOR_BUY_BUNDLE: Gas = Gas(20_000_000_000_000);
+//resolve_bundle_purchase without handing over the tokens: checking the payouts and paying up to 10 accounts per token
+const GAS_FOR_RESOLVE_BUNDLE_PURCHASE: Gas = Gas(45_000_000_000_000);
+const GAS_FOR_RESOLVE_HANDOVER: Gas = Gas(10_000_000_000_000);
+//resolve_purchase, plus putting the unit back on the offer if a collection offer fill failed
+const GAS_FOR_RESOLVE_COLLECTION_OFFER_FILL: Gas = Gas(GAS_FOR_RESOLVE_PURCHASE.0 + 5_000_000_000_000);
+//the callback of a purchase made by a sweep only pays out NEAR, or returns a token in custody to its owner, so it
+//doesn't need the GAS of a regular purchase
+const GAS_FOR_RESOLVE_SWEEP_PURCHASE: Gas = Gas(35_000_000_000_000);
+//GAS a single purchase of a sweep needs: the transfer, its callback and removing the sale
+const GAS_FOR_SWEEP_ITEM: Gas = Gas(GAS_FOR_NFT_TRANSFER.0 + GAS_FOR_RESOLVE_SWEEP_PURCHASE.0 + 15_000_000_000_000);
+//GAS sweep uses itself to pick the listings and refund the deposit
+const GAS_FOR_SWEEP: Gas = Gas(20_000_000_000_000);
+//with the 300 TGas a transaction can attach at most, that's how many purchases fit in a sweep
+pub const MAX_SWEEP_ITEMS: u32 = 4;
 
 //the minimum storage to have a sale on the contract.
 const STORAGE_PER_SALE: u128 = 1000 * STORAGE_PRICE_PER_BYTE;
 
+//how far a late bid pushes back the end of an auction (10 minutes in nanoseconds)
+const AUCTION_EXTENSION: u64 = 10 * 60 * 1_000_000_000;
+
 //every sale will have a unique ID which is `CONTRACT + DELIMITER + TOKEN_ID`
 static DELIMETER: &str = ".";
 
 //Creating custom types to use within the contract. This makes things more readable. 
-pub type SalePriceInYoctoNear = U128;
 pub type TokenId = String;
 pub type FungibleTokenId = AccountId;
 pub type ContractAndTokenId = String;
@@ -42,6 +87,15 @@ pub struct Payout {
     pub payout: HashMap<AccountId, U128>,
 } 
 
+//the part of the token returned by nft_token that the market needs to check who owns it and who's approved for it
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct JsonToken {
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub approved_account_ids: Option<HashMap<AccountId, u64>>,
+}
+
 
 //main contract struct to store all the information
 #[near_bindgen]
@@ -49,6 +103,12 @@ pub struct Payout {
 pub struct Contract {
     //keep track of the owner of the contract
     pub owner_id: AccountId,
+
+    //account the owner proposed to hand the contract over to
+    pub pending_owner_id: Option<AccountId>,
+
+    //roles the owner delegated to other accounts
+    pub roles: UnorderedMap<AccountId, Vec<Role>>,
     
     /*
         to keep track of the sales, we map the ContractAndTokenId to a Sale. 
@@ -63,17 +123,77 @@ pub struct Contract {
     //keep track of all the token IDs for sale for a given contract
     pub by_nft_contract_id: LookupMap<AccountId, UnorderedSet<TokenId>>,
 
+    //keep track of the fixed price sales of a given contract in a given currency, sorted by price
+    pub sales_by_price: LookupMap<(AccountId, Currency), OrderBook>,
+
+    //keep track of the offers on every unique sale ID, by buyer
+    pub offers_by_token: LookupMap<ContractAndTokenId, UnorderedMap<AccountId, Offer>>,
+
+    //keep track of all the unique sale IDs a buyer made offers on
+    pub offers_by_buyer_id: LookupMap<AccountId, UnorderedSet<ContractAndTokenId>>,
+
+    //keep track of the token IDs with offers for every nft contract
+    pub offered_token_ids_by_nft_contract_id: LookupMap<AccountId, UnorderedSet<TokenId>>,
+
+    //keep track of the collection offers by their ID
+    pub collection_offers: UnorderedMap<CollectionOfferId, CollectionOffer>,
+
+    //keep track of the collection offer IDs for a given nft contract
+    pub collection_offers_by_nft_contract_id: LookupMap<AccountId, UnorderedSet<CollectionOfferId>>,
+
+    //keep track of the collection offer IDs of a given bidder
+    pub collection_offers_by_bidder_id: LookupMap<AccountId, UnorderedSet<CollectionOfferId>>,
+
+    //keep track of the collection offers of a given nft contract, sorted by amount
+    pub collection_offers_by_amount: LookupMap<AccountId, CollectionOfferBook>,
+
+    //ID given to the next collection offer
+    pub next_collection_offer_id: CollectionOfferId,
+
+    //keep track of the bundles by their ID
+    pub bundles: UnorderedMap<BundleId, Bundle>,
+
+    //keep track of the bundle IDs for every account ID
+    pub bundles_by_owner_id: LookupMap<AccountId, UnorderedSet<BundleId>>,
+
+    //keep track of the IDs of the bundles holding a token of a given nft contract
+    pub bundles_by_nft_contract_id: LookupMap<AccountId, UnorderedSet<BundleId>>,
+
+    //ID given to the next bundle
+    pub next_bundle_id: BundleId,
+
+    //tokens of a bundle the market couldn't hand over, mapped to the account that can claim them
+    pub pending_handovers: LookupMap<ContractAndTokenId, AccountId>,
+
     //keep track of the storage that accounts have payed
     pub storage_deposits: LookupMap<AccountId, Balance>,
 
-    //percentage to take as fee from every 'buy' transaction
-    pub fee: U128,
+    //fee taken from the price of every sale, in basis points (10_000 is 100%)
+    pub fee_bps: u16,
+
+    //lower fees for sellers with more sales
+    pub fee_tiers: Vec<FeeTier>,
+
+    //total NEAR every seller sold whitelisted cards for, used for the fee tiers
+    pub volume_by_seller_id: LookupMap<AccountId, Balance>,
     
     //where to transfer the fee
     pub fee_recipient: AccountId,
 
+    //fungible tokens (USN, IOU-USN...) that sales can be listed and paid in besides NEAR
+    pub approved_ft_token_ids: UnorderedSet<FungibleTokenId>,
+
     // whitelist to 
-    pub whitelist: UnorderedMap<AccountId, U128>
+    pub whitelist: UnorderedMap<AccountId, WhitelistEntry>,
+
+    //collections removed from the whitelist that still have sales to purge. Their sales can't be bought
+    pub delisted_nft_contract_ids: LookupSet<AccountId>,
+
+    //sale statistics of every collection, per currency
+    pub collection_stats: LookupMap<AccountId, Vec<CollectionStats>>,
+
+    //last trades of every unique sale ID
+    pub trade_history: LookupMap<ContractAndTokenId, Vec<Trade>>,
 }
 
 /// Helper structure to for keys of the persistent collections.
@@ -87,8 +207,34 @@ pub enum StorageKey {
     ByNFTTokenType,
     ByNFTTokenTypeInner { token_type_hash: CryptoHash },
     FTTokenIds,
+    OffersByToken,
+    OffersByTokenInner { token_hash: CryptoHash },
+    OffersByBuyerId,
+    OffersByBuyerIdInner { account_id_hash: CryptoHash },
+    CollectionOffers,
+    CollectionOffersByNFTContractId,
+    CollectionOffersByNFTContractIdInner { account_id_hash: CryptoHash },
+    CollectionOffersByBidderId,
+    CollectionOffersByBidderIdInner { account_id_hash: CryptoHash },
+    Bundles,
+    BundlesByOwnerId,
+    BundlesByOwnerIdInner { account_id_hash: CryptoHash },
     StorageDeposits,
     Whitelists,
+    DelistedNFTContractIds,
+    Roles,
+    VolumeBySellerId,
+    CollectionStats,
+    TradeHistory,
+    SalesByPrice,
+    SalesByPriceInner { order_book_hash: CryptoHash },
+    BundlesByNFTContractId,
+    BundlesByNFTContractIdInner { account_id_hash: CryptoHash },
+    OfferedTokenIdsByNFTContractId,
+    OfferedTokenIdsByNFTContractIdInner { account_id_hash: CryptoHash },
+    PendingHandovers,
+    CollectionOffersByAmount,
+    CollectionOffersByAmountInner { account_id_hash: CryptoHash },
 }
 
 #[near_bindgen]
@@ -99,21 +245,50 @@ impl Contract {
         that's passed in
     */
     #[init]
-    pub fn new(owner_id: AccountId, fee:U128, fee_recipient: AccountId) -> Self {
-        let this = Self {
+    pub fn new(owner_id: AccountId, fee_bps: u16, fee_recipient: AccountId, approved_ft_token_ids: Vec<FungibleTokenId>) -> Self {
+        let mut this = Self {
             //set the owner_id field equal to the passed in owner_id. 
             owner_id,
+            pending_owner_id: None,
+            roles: UnorderedMap::new(StorageKey::Roles),
 
             //Storage keys are simply the prefixes used for the collections. This helps avoid data collision
             sales: UnorderedMap::new(StorageKey::Sales),
             by_owner_id: LookupMap::new(StorageKey::ByOwnerId),
             by_nft_contract_id: LookupMap::new(StorageKey::ByNFTContractId),
+            sales_by_price: LookupMap::new(StorageKey::SalesByPrice),
+            offers_by_token: LookupMap::new(StorageKey::OffersByToken),
+            offers_by_buyer_id: LookupMap::new(StorageKey::OffersByBuyerId),
+            offered_token_ids_by_nft_contract_id: LookupMap::new(StorageKey::OfferedTokenIdsByNFTContractId),
+            collection_offers: UnorderedMap::new(StorageKey::CollectionOffers),
+            collection_offers_by_nft_contract_id: LookupMap::new(StorageKey::CollectionOffersByNFTContractId),
+            collection_offers_by_bidder_id: LookupMap::new(StorageKey::CollectionOffersByBidderId),
+            collection_offers_by_amount: LookupMap::new(StorageKey::CollectionOffersByAmount),
+            next_collection_offer_id: 0,
+            bundles: UnorderedMap::new(StorageKey::Bundles),
+            bundles_by_owner_id: LookupMap::new(StorageKey::BundlesByOwnerId),
+            bundles_by_nft_contract_id: LookupMap::new(StorageKey::BundlesByNFTContractId),
+            next_bundle_id: 0,
+            pending_handovers: LookupMap::new(StorageKey::PendingHandovers),
             storage_deposits: LookupMap::new(StorageKey::StorageDeposits),
-            fee,
+            fee_bps,
+            fee_tiers: Vec::new(),
+            volume_by_seller_id: LookupMap::new(StorageKey::VolumeBySellerId),
             fee_recipient,
+            approved_ft_token_ids: UnorderedSet::new(StorageKey::FTTokenIds),
             whitelist: UnorderedMap::new(StorageKey::Whitelists),
+            delisted_nft_contract_ids: LookupSet::new(StorageKey::DelistedNFTContractIds),
+            collection_stats: LookupMap::new(StorageKey::CollectionStats),
+            trade_history: LookupMap::new(StorageKey::TradeHistory),
         };
 
+        assert!(fee_bps <= MAX_FEE_BPS, "Fee can't be more than {} basis points", MAX_FEE_BPS);
+
+        //add the fungible tokens accepted as payment from the start
+        for ft_token_id in approved_ft_token_ids {
+            this.approved_ft_token_ids.insert(&ft_token_id);
+        }
+
         //return the Contract object
         this
     }
@@ -161,11 +336,9 @@ impl Contract {
         //get the amount that the user has by removing them from the map. If they're not in the map, default to 0
         let mut amount = self.storage_deposits.remove(&owner_id).unwrap_or(0);
         
-        //how many sales is that user taking up currently. This returns a set
-        let sales = self.by_owner_id.get(&owner_id);
-        //get the length of that set. 
-        let len = sales.map(|s| s.len()).unwrap_or_default();
-        //how much NEAR is being used up for all the current sales on the account 
+        //how many sales and offers is that user taking up currently
+        let len = self.internal_storage_entries(&owner_id);
+        //how much NEAR is being used up for all the current sales and offers on the account 
         let diff = u128::from(len) * STORAGE_PER_SALE;
 
         //the excess to withdraw is the total storage paid - storage being used up.
@@ -193,4 +366,867 @@ impl Contract {
     pub fn storage_balance_of(&self, account_id: AccountId) -> U128 {
         U128(self.storage_deposits.get(&account_id).unwrap_or(0))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ft_callbacks::FungibleTokenReceiver;
+    use near_sdk::test_utils::{get_created_receipts, get_logs, VMContextBuilder};
+    use near_sdk::mock::VmAction;
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+
+    const MARKET: &str = "market";
+    const OWNER: &str = "owner";
+    const SELLER: &str = "seller";
+    const NFT: &str = "cards";
+    const TOKEN: &str = "TokenizedCard-1";
+    const ONE_NEAR: u128 = 1_000_000_000_000_000_000_000_000;
+
+    fn account(account_id: &str) -> AccountId {
+        account_id.parse().unwrap()
+    }
+
+    fn context(predecessor_id: &str) -> VMContextBuilder {
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id(account(MARKET))
+            .signer_account_id(account(predecessor_id))
+            .predecessor_account_id(account(predecessor_id));
+        context
+    }
+
+    fn contract() -> Contract {
+        testing_env!(context(OWNER).build());
+        //every contract starts from empty storage, as if it was just deployed
+        near_sdk::mock::with_mocked_blockchain(|blockchain| blockchain.take_storage());
+        Contract::new(account(OWNER), 250, account("treasury"), vec![])
+    }
+
+    fn whitelist(contract: &mut Contract, nft_contract_id: &str, fee_bps: Option<u16>) {
+        testing_env!(context(OWNER).attached_deposit(1).build());
+        contract.allowlist_card(account(nft_contract_id), U128(0), fee_bps, None);
+    }
+
+    //lists a fixed price sale the way nft_approve does, with the storage it needs already paid by its owner
+    fn list(contract: &mut Contract, owner_id: &str, token_id: &str, price: Balance) {
+        let storage = contract.storage_deposits.get(&account(owner_id)).unwrap_or(0);
+        contract.storage_deposits.insert(&account(owner_id), &(storage + STORAGE_PER_SALE));
+        let sale_args = SaleArgs { price: U128(price), currency: None, auction: None, dutch_auction: None, expires_at: None };
+        contract.internal_list_sale(account(NFT), token_id.to_string(), account(owner_id), 1, sale_args, false);
+    }
+
+    fn nft_token_result(owner_id: &str, approval_id: u64) -> PromiseResult {
+        let token = JsonToken {
+            token_id: TOKEN.to_string(),
+            owner_id: account(owner_id),
+            approved_account_ids: Some(HashMap::from([(account(MARKET), approval_id)])),
+        };
+        PromiseResult::Successful(near_sdk::serde_json::to_vec(&Some(token)).unwrap())
+    }
+
+    fn contract_and_token_id() -> ContractAndTokenId {
+        format!("{}{}{}", NFT, DELIMETER, TOKEN)
+    }
+
+    fn sale(price: Balance, sale_type: SaleType) -> Sale {
+        Sale {
+            owner_id: account(SELLER),
+            approval_id: 1,
+            nft_contract_id: NFT.to_string(),
+            token_id: TOKEN.to_string(),
+            price: U128(price),
+            currency: Currency::Near,
+            sale_type,
+            expires_at: None,
+            custodial: false,
+        }
+    }
+
+    //NEAR transfers scheduled so far, as (receiver, amount)
+    fn transfers() -> Vec<(AccountId, Balance)> {
+        get_created_receipts()
+            .into_iter()
+            .flat_map(|receipt| {
+                let receiver_id = receipt.receiver_id;
+                receipt.actions.into_iter().filter_map(move |action| match action {
+                    VmAction::Transfer { deposit } => Some((receiver_id.clone(), deposit)),
+                    _ => None,
+                })
+            })
+            .collect()
+    }
+
+    fn auction(end_at: u64) -> SaleType {
+        SaleType::Auction(Auction {
+            start_at: U64(0),
+            end_at: U64(end_at),
+            min_bid_increment: U128(ONE_NEAR),
+            highest_bid: None,
+        })
+    }
+
+    fn highest_bid(contract: &Contract) -> (Option<Bid>, u64) {
+        match contract.sales.get(&contract_and_token_id()).unwrap().sale_type {
+            SaleType::Auction(auction) => (auction.highest_bid, auction.end_at.0),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn outbid_bidder_is_refunded() {
+        let mut contract = contract();
+        let day = 24 * 60 * 60 * 1_000_000_000;
+        testing_env!(context("alice").block_timestamp(1).build());
+        contract.internal_place_bid(&contract_and_token_id(), sale(10 * ONE_NEAR, auction(day)), account("alice"), 10 * ONE_NEAR);
+        assert!(transfers().is_empty());
+
+        testing_env!(context("bob").block_timestamp(2).build());
+        let sale = contract.sales.get(&contract_and_token_id()).unwrap();
+        contract.internal_place_bid(&contract_and_token_id(), sale, account("bob"), 11 * ONE_NEAR);
+
+        assert_eq!(transfers(), vec![(account("alice"), 10 * ONE_NEAR)]);
+        let (bid, end_at) = highest_bid(&contract);
+        let bid = bid.unwrap();
+        assert_eq!((bid.bidder_id, bid.amount.0), (account("bob"), 11 * ONE_NEAR));
+        //bids long before the end don't extend the auction
+        assert_eq!(end_at, day);
+    }
+
+    #[test]
+    #[should_panic(expected = "Bid must be at least")]
+    fn bid_must_beat_highest_bid_by_increment() {
+        let mut contract = contract();
+        testing_env!(context("alice").block_timestamp(1).build());
+        contract.internal_place_bid(&contract_and_token_id(), sale(ONE_NEAR, auction(1_000)), account("alice"), ONE_NEAR);
+
+        let sale = contract.sales.get(&contract_and_token_id()).unwrap();
+        contract.internal_place_bid(&contract_and_token_id(), sale, account("bob"), ONE_NEAR + 1);
+    }
+
+    #[test]
+    fn dutch_price_declines_without_overflow() {
+        let day = 24 * 60 * 60 * 1_000_000_000;
+        let start_at = 1_700_000_000_000_000_000;
+        let mut dutch_auction = DutchAuction {
+            floor_price: U128(ONE_NEAR),
+            start_at: U64(start_at),
+            end_at: U64(start_at + day),
+            step_interval: None,
+        };
+        let start_price = 100 * ONE_NEAR;
+
+        assert_eq!(dutch_auction.price_at(start_price, start_at), start_price);
+        assert_eq!(dutch_auction.price_at(start_price, start_at + day / 4), 75 * ONE_NEAR + ONE_NEAR / 4);
+        assert_eq!(dutch_auction.price_at(start_price, start_at + day / 2), 50 * ONE_NEAR + ONE_NEAR / 2);
+        assert_eq!(dutch_auction.price_at(start_price, start_at + day - 1), ONE_NEAR + (99 * ONE_NEAR).div_ceil(day as u128));
+        assert_eq!(dutch_auction.price_at(start_price, start_at + day), ONE_NEAR);
+
+        //hourly steps hold the price until the next full hour
+        dutch_auction.step_interval = Some(U64(day / 24));
+        assert_eq!(dutch_auction.price_at(start_price, start_at + day / 24 - 1), start_price);
+        assert_eq!(
+            dutch_auction.price_at(start_price, start_at + day / 2 + day / 48),
+            dutch_auction.price_at(start_price, start_at + day / 2)
+        );
+    }
+
+    #[test]
+    fn purge_collection_drops_bundles_and_refunds_offers() {
+        let mut contract = contract();
+        contract.internal_add_bundle(&Bundle {
+            bundle_id: U64(0),
+            owner_id: account(SELLER),
+            items: vec![
+                BundleItem { nft_contract_id: account(NFT), token_id: TOKEN.to_string(), approval_id: 1 },
+                BundleItem { nft_contract_id: account("other"), token_id: TOKEN.to_string(), approval_id: 1 },
+            ],
+            price: U128(ONE_NEAR),
+            expires_at: None,
+        });
+        contract.internal_add_collection_offer(&CollectionOffer {
+            offer_id: U64(0),
+            bidder_id: account("alice"),
+            nft_contract_id: account(NFT),
+            amount: U128(ONE_NEAR),
+            quantity: 3,
+            expires_at: None,
+        });
+        for buyer_id in ["bob", "carol"] {
+            contract.internal_add_offer(
+                &contract_and_token_id(),
+                Offer {
+                    buyer_id: account(buyer_id),
+                    nft_contract_id: account(NFT),
+                    token_id: TOKEN.to_string(),
+                    amount: U128(2 * ONE_NEAR),
+                    expires_at: None,
+                },
+            );
+        }
+
+        testing_env!(context(OWNER).attached_deposit(1).build());
+        contract.disallow_card(account(NFT));
+        assert!(contract.is_collection_delisted(account(NFT)));
+
+        //the limit is shared by every kind of entry, the rest waits for the next call
+        assert_eq!(contract.purge_collection(account(NFT), 3).0, 3);
+        assert!(contract.get_bundle(U64(0)).is_none());
+        assert!(contract.bundles_by_nft_contract_id.get(&account("other")).is_none());
+        assert!(contract.is_collection_delisted(account(NFT)));
+        assert_eq!(contract.purge_collection(account(NFT), 10).0, 1);
+
+        let mut transfers = transfers();
+        transfers.sort();
+        assert_eq!(
+            transfers,
+            vec![(account("alice"), 3 * ONE_NEAR), (account("bob"), 2 * ONE_NEAR), (account("carol"), 2 * ONE_NEAR)]
+        );
+        assert!(contract.get_offers_by_token(contract_and_token_id(), None, Some(10)).is_empty());
+        assert!(!contract.is_collection_delisted(account(NFT)));
+    }
+
+    #[test]
+    fn min_price_is_checked_in_the_listing_currency() {
+        let mut contract = contract();
+        let usdc = Currency::FungibleToken(account("usdc"));
+        testing_env!(context(OWNER).attached_deposit(1).build());
+        contract.allowlist_card(account(NFT), U128(ONE_NEAR), None, None);
+
+        assert!(contract.internal_check_listing(&account(NFT), U128(ONE_NEAR), &Currency::Near).is_ok());
+        assert!(contract.internal_check_listing(&account(NFT), U128(ONE_NEAR - 1), &Currency::Near).is_err());
+        //a NEAR min price says nothing about an amount of fungible tokens
+        assert!(contract.internal_check_listing(&account(NFT), U128(ONE_NEAR), &usdc).is_err());
+
+        let ft_min_prices = HashMap::from([(account("usdc"), U128(5_000_000))]);
+        contract.allowlist_card(account(NFT), U128(ONE_NEAR), None, Some(ft_min_prices));
+        assert!(contract.internal_check_listing(&account(NFT), U128(5_000_000), &usdc).is_ok());
+        assert!(contract.internal_check_listing(&account(NFT), U128(4_999_999), &usdc).is_err());
+    }
+
+    #[test]
+    fn sales_by_price_are_paginated_from_the_cheapest() {
+        let mut contract = contract();
+        for (token_id, price) in [("a", 3), ("b", 1), ("c", 2), ("d", 2), ("e", 5)] {
+            let sale = Sale { token_id: token_id.to_string(), ..sale(price * ONE_NEAR, SaleType::FixedPrice) };
+            contract.sales.insert(&format!("{}{}{}", NFT, DELIMETER, token_id), &sale);
+            contract.internal_add_to_order_book(&sale);
+        }
+        let token_ids = |sales: Vec<Sale>| sales.into_iter().map(|sale| sale.token_id).collect::<Vec<_>>();
+
+        assert_eq!(contract.get_floor_price(account(NFT), None), Some(U128(ONE_NEAR)));
+        assert_eq!(
+            token_ids(contract.get_sales_by_price(account(NFT), None, None, None, Some(10), None)),
+            vec!["b", "c", "d", "a", "e"]
+        );
+        assert_eq!(
+            token_ids(contract.get_sales_by_price(
+                account(NFT),
+                Some(U128(2 * ONE_NEAR)),
+                Some(U128(3 * ONE_NEAR)),
+                Some(U128(1)),
+                Some(10),
+                None
+            )),
+            vec!["d", "a"]
+        );
+        assert!(contract.get_sales_by_price(account(NFT), None, None, None, Some(10), Some(Currency::FungibleToken(account("usdc")))).is_empty());
+    }
+
+    #[test]
+    fn fee_uses_the_lowest_of_the_collection_fee_and_the_reached_tier() {
+        let mut contract = contract();
+        contract.fee_tiers = vec![
+            FeeTier { min_volume: U128(100 * ONE_NEAR), fee_bps: 50 },
+            FeeTier { min_volume: U128(10 * ONE_NEAR), fee_bps: 200 },
+        ];
+        whitelist(&mut contract, "discounted", Some(100));
+        let seller = account(SELLER);
+        let sale_of = |nft_contract_id: &str, price: Balance| Purchase {
+            nft_contract_id: account(nft_contract_id),
+            price: U128(price),
+            ..purchase(false)
+        };
+
+        //no tier reached: the default fee, or the collection's override
+        assert_eq!(contract.internal_fee_amount(&seller, &account(NFT), 100 * ONE_NEAR), 25 * ONE_NEAR / 10);
+        assert_eq!(contract.internal_fee_amount(&seller, &account("discounted"), 100 * ONE_NEAR), ONE_NEAR);
+
+        //sales of cards that aren't whitelisted don't count towards the volume
+        contract.internal_record_seller_volume(&sale_of(NFT, 1_000 * ONE_NEAR));
+        assert_eq!(contract.get_seller_volume(seller.clone()).0, 0);
+
+        //the highest tier reached applies when it's lower
+        contract.internal_record_seller_volume(&sale_of("discounted", 10 * ONE_NEAR));
+        assert_eq!(contract.get_fee_for(seller.clone(), account(NFT)), 200);
+        assert_eq!(contract.get_fee_for(seller.clone(), account("discounted")), 100);
+        contract.internal_record_seller_volume(&sale_of("discounted", 90 * ONE_NEAR - 1));
+        assert_eq!(contract.get_fee_for(seller.clone(), account(NFT)), 200);
+        contract.internal_record_seller_volume(&sale_of("discounted", 1));
+        assert_eq!(contract.get_fee_for(seller.clone(), account(NFT)), 50);
+        assert_eq!(contract.internal_fee_amount(&seller, &account("discounted"), 100 * ONE_NEAR), ONE_NEAR / 2);
+
+        //rounded down to the yocto
+        assert_eq!(contract.internal_fee_amount(&seller, &account(NFT), 399), 1);
+    }
+
+    fn purchase(custodial: bool) -> Purchase {
+        Purchase {
+            nft_contract_id: account(NFT),
+            token_id: TOKEN.to_string(),
+            seller_id: account(SELLER),
+            buyer_id: account("buyer"),
+            price: U128(10 * ONE_NEAR),
+            fee: U128(ONE_NEAR / 4),
+            deposit: U128(12 * ONE_NEAR),
+            custodial,
+        }
+    }
+
+    fn payout_result(payout: &[(&str, Balance)]) -> PromiseResult {
+        let payout = payout.iter().map(|(account_id, amount)| (account(account_id), U128(*amount))).collect();
+        PromiseResult::Successful(near_sdk::serde_json::to_vec(&Payout { payout }).unwrap())
+    }
+
+    //runs the next calls as a callback of the market receiving the given promise results
+    fn callback_env(promise_results: Vec<PromiseResult>) {
+        testing_env!(
+            context(MARKET).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            promise_results
+        );
+    }
+
+    #[test]
+    fn resolve_purchase_charges_the_price_and_refunds_the_rest() {
+        let mut contract = contract();
+        whitelist(&mut contract, NFT, None);
+        callback_env(vec![payout_result(&[(SELLER, 9 * ONE_NEAR), ("artist", 3 * ONE_NEAR / 4)])]);
+        let result = contract.resolve_purchase(purchase(false));
+
+        assert_eq!((result.charged.0, result.refunded.0), (10 * ONE_NEAR, 2 * ONE_NEAR));
+        let mut transfers = transfers();
+        transfers.sort();
+        assert_eq!(
+            transfers,
+            vec![
+                (account("artist"), 3 * ONE_NEAR / 4),
+                (account("buyer"), 2 * ONE_NEAR),
+                (account(SELLER), 9 * ONE_NEAR),
+                (account("treasury"), ONE_NEAR / 4),
+            ]
+        );
+        assert_eq!(contract.get_seller_volume(account(SELLER)).0, 10 * ONE_NEAR);
+    }
+
+    #[test]
+    fn resolve_purchase_refunds_everything_without_a_valid_payout() {
+        //a failed transfer, and a payout that doesn't add up to the price minus the fee
+        for promise_result in [PromiseResult::Failed, payout_result(&[(SELLER, 10 * ONE_NEAR)])] {
+            let mut contract = contract();
+            whitelist(&mut contract, NFT, None);
+            callback_env(vec![promise_result]);
+            let result = contract.resolve_purchase(purchase(false));
+
+            assert_eq!((result.charged.0, result.refunded.0), (0, 12 * ONE_NEAR));
+            assert_eq!(transfers(), vec![(account("buyer"), 12 * ONE_NEAR)]);
+            assert_eq!(contract.get_seller_volume(account(SELLER)).0, 0);
+        }
+    }
+
+    #[test]
+    fn custody_payout_goes_to_the_seller() {
+        let contract = contract();
+        let payout = HashMap::from([
+            (account(MARKET), U128(9 * ONE_NEAR)),
+            (account(SELLER), U128(ONE_NEAR / 2)),
+            (account("artist"), U128(ONE_NEAR / 4)),
+        ]);
+
+        //the market owns a token in custody, so its share is the seller's, added to what the seller already gets
+        let settled = contract.internal_settle_custody(&purchase(true), Some(payout.clone())).unwrap();
+        assert_eq!(
+            settled,
+            HashMap::from([(account(SELLER), U128(9 * ONE_NEAR + ONE_NEAR / 2)), (account("artist"), U128(ONE_NEAR / 4))])
+        );
+
+        //the payout of a token that isn't in custody is left as it is
+        assert_eq!(contract.internal_settle_custody(&purchase(false), Some(payout)).unwrap().len(), 3);
+        assert!(contract.internal_settle_custody(&purchase(false), None).is_none());
+        assert!(get_created_receipts().is_empty());
+    }
+
+    #[test]
+    fn failed_custody_purchase_returns_the_token_to_the_seller() {
+        let contract = contract();
+        assert!(contract.internal_settle_custody(&purchase(true), None).is_none());
+
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, account(NFT));
+        match &receipts[0].actions[0] {
+            VmAction::FunctionCall { function_name, args, .. } => {
+                assert_eq!(function_name, "nft_transfer");
+                let args: near_sdk::serde_json::Value = near_sdk::serde_json::from_slice(args).unwrap();
+                assert_eq!(args["receiver_id"], SELLER);
+            }
+            _ => panic!("Expected a nft_transfer call"),
+        }
+    }
+
+    fn bundle() -> Bundle {
+        Bundle {
+            bundle_id: U64(0),
+            owner_id: account(SELLER),
+            items: vec![
+                BundleItem { nft_contract_id: account(NFT), token_id: TOKEN.to_string(), approval_id: 1 },
+                BundleItem { nft_contract_id: account(NFT), token_id: "TokenizedCard-2".to_string(), approval_id: 1 },
+            ],
+            price: U128(2 * ONE_NEAR),
+            expires_at: None,
+        }
+    }
+
+    //nft_transfer calls the market made, as (token ID, receiver ID)
+    fn nft_transfers() -> Vec<(String, String)> {
+        get_created_receipts()
+            .into_iter()
+            .flat_map(|receipt| receipt.actions)
+            .filter_map(|action| match action {
+                VmAction::FunctionCall { function_name, args, .. } if function_name == "nft_transfer" => {
+                    let args: near_sdk::serde_json::Value = near_sdk::serde_json::from_slice(&args).unwrap();
+                    Some((args["token_id"].as_str()?.to_string(), args["receiver_id"].as_str()?.to_string()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn failed_bundle_item_refunds_the_whole_bundle() {
+        let mut contract = contract();
+        whitelist(&mut contract, NFT, None);
+        //the first token reached the market, the second didn't
+        callback_env(vec![payout_result(&[(SELLER, ONE_NEAR)]), PromiseResult::Failed]);
+        let charged = contract.resolve_bundle_purchase(account("buyer"), bundle(), vec![U128(0), U128(0)]);
+
+        //nobody is paid, the buyer gets the full price back and the token the market got goes back to the owner
+        assert_eq!(charged.0, 0);
+        assert_eq!(transfers(), vec![(account("buyer"), 2 * ONE_NEAR)]);
+        assert_eq!(nft_transfers(), vec![(TOKEN.to_string(), SELLER.to_string())]);
+        assert_eq!(contract.get_seller_volume(account(SELLER)).0, 0);
+    }
+
+    #[test]
+    fn token_that_wasnt_handed_over_can_be_claimed() {
+        let mut contract = contract();
+        let items = bundle().items;
+        let pending = format!("{}{}{}", NFT, DELIMETER, "TokenizedCard-2");
+        callback_env(vec![PromiseResult::Successful(vec![]), PromiseResult::Failed]);
+        contract.resolve_handover(account("buyer"), items);
+
+        assert_eq!(contract.get_pending_handover(format!("{}{}{}", NFT, DELIMETER, TOKEN)), None);
+        assert_eq!(contract.get_pending_handover(pending.clone()), Some(account("buyer")));
+
+        testing_env!(context("buyer").attached_deposit(1).build());
+        contract.claim_token(account(NFT), "TokenizedCard-2".to_string());
+        assert_eq!(contract.get_pending_handover(pending), None);
+        assert_eq!(nft_transfers(), vec![("TokenizedCard-2".to_string(), "buyer".to_string())]);
+    }
+
+    #[test]
+    fn stale_sale_is_taken_down_and_its_storage_rewarded() {
+        //the token changed hands, or the market was approved again with a new approval ID
+        for promise_result in [nft_token_result("alice", 1), nft_token_result(SELLER, 2)] {
+            let mut contract = contract();
+            whitelist(&mut contract, NFT, None);
+            list(&mut contract, SELLER, TOKEN, ONE_NEAR);
+            callback_env(vec![promise_result]);
+
+            assert!(contract.resolve_verify_sale(contract_and_token_id(), 1, account("caller")));
+            assert!(contract.get_sale(contract_and_token_id()).is_none());
+            assert_eq!(transfers(), vec![(account("caller"), STORAGE_PER_SALE)]);
+            assert_eq!(contract.storage_deposits.get(&account(SELLER)), Some(0));
+        }
+    }
+
+    #[test]
+    fn valid_sale_is_kept_without_reward() {
+        let mut contract = contract();
+        whitelist(&mut contract, NFT, None);
+        list(&mut contract, SELLER, TOKEN, ONE_NEAR);
+        callback_env(vec![nft_token_result(SELLER, 1)]);
+
+        assert!(!contract.resolve_verify_sale(contract_and_token_id(), 1, account("caller")));
+        assert!(contract.get_sale(contract_and_token_id()).is_some());
+        assert!(transfers().is_empty());
+        assert_eq!(contract.storage_deposits.get(&account(SELLER)), Some(STORAGE_PER_SALE));
+
+        //a token put in custody since the check is listed with approval ID 0 and isn't taken down either
+        let custody_sale = Sale { approval_id: 0, custodial: true, ..contract.get_sale(contract_and_token_id()).unwrap() };
+        contract.sales.insert(&contract_and_token_id(), &custody_sale);
+        callback_env(vec![nft_token_result(MARKET, 0)]);
+        assert!(!contract.resolve_verify_sale(contract_and_token_id(), 0, account("caller")));
+        assert!(contract.get_sale(contract_and_token_id()).is_some());
+    }
+
+    //token IDs bought with nft_transfer_payout, in the order they were bought
+    fn bought_token_ids() -> Vec<String> {
+        get_created_receipts()
+            .into_iter()
+            .flat_map(|receipt| receipt.actions)
+            .filter_map(|action| match action {
+                VmAction::FunctionCall { function_name, args, .. } if function_name == "nft_transfer_payout" => {
+                    let args: near_sdk::serde_json::Value = near_sdk::serde_json::from_slice(&args).unwrap();
+                    Some(args["token_id"].as_str()?.to_string())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn sweep_env(deposit: Balance) {
+        testing_env!(context("buyer").attached_deposit(deposit).prepaid_gas(Gas(300_000_000_000_000)).build());
+    }
+
+    #[test]
+    fn sweep_buys_the_cheapest_listings_within_budget() {
+        let mut contract = contract();
+        whitelist(&mut contract, NFT, None);
+        for (token_id, price) in [("a", 3), ("b", 1), ("c", 2), ("d", 4)] {
+            list(&mut contract, SELLER, token_id, price * ONE_NEAR);
+        }
+        //the cheapest listing is the buyer's own
+        list(&mut contract, "buyer", "e", ONE_NEAR / 2);
+
+        //"b" and "c" fit in the budget, "a" would go over it
+        sweep_env(10 * ONE_NEAR);
+        assert_eq!(contract.sweep(account(NFT), 4, U128(5 * ONE_NEAR)).0, 2);
+        assert_eq!(bought_token_ids(), vec!["b", "c"]);
+        //the deposit beyond the 3 NEAR spent is refunded right away
+        assert_eq!(transfers(), vec![(account("buyer"), 7 * ONE_NEAR)]);
+        assert!(contract.get_sale(format!("{}{}e", NFT, DELIMETER)).is_some());
+        assert!(contract.get_sale(format!("{}{}a", NFT, DELIMETER)).is_some());
+    }
+
+    #[test]
+    fn sweep_stops_at_max_count() {
+        let mut contract = contract();
+        whitelist(&mut contract, NFT, None);
+        for (token_id, price) in [("a", 3), ("b", 1), ("c", 2)] {
+            list(&mut contract, SELLER, token_id, price * ONE_NEAR);
+        }
+
+        sweep_env(6 * ONE_NEAR);
+        assert_eq!(contract.sweep(account(NFT), 2, U128(6 * ONE_NEAR)).0, 2);
+        assert_eq!(bought_token_ids(), vec!["b", "c"]);
+        assert_eq!(transfers(), vec![(account("buyer"), 3 * ONE_NEAR)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "A sweep buys between 1 and 4 listings")]
+    fn sweep_is_limited_to_what_fits_in_a_transaction() {
+        let mut contract = contract();
+        whitelist(&mut contract, NFT, None);
+        sweep_env(ONE_NEAR);
+        contract.sweep(account(NFT), MAX_SWEEP_ITEMS + 1, U128(ONE_NEAR));
+    }
+
+    fn collection_offer(offer_id: u64, amount: Balance, quantity: u32, expires_at: Option<u64>) -> CollectionOffer {
+        CollectionOffer {
+            offer_id: U64(offer_id),
+            bidder_id: account("alice"),
+            nft_contract_id: account(NFT),
+            amount: U128(amount),
+            quantity,
+            expires_at: expires_at.map(U64),
+        }
+    }
+
+    #[test]
+    fn failed_fill_puts_the_unit_back_on_the_collection_offer() {
+        let mut contract = contract();
+        whitelist(&mut contract, NFT, None);
+        contract.internal_add_collection_offer(&collection_offer(0, ONE_NEAR, 1, None));
+        let fill = || Purchase { buyer_id: account("alice"), price: U128(ONE_NEAR), fee: U128(0), deposit: U128(ONE_NEAR), ..purchase(false) };
+
+        //the unit stays escrowed on the offer, nothing is refunded
+        callback_env(vec![PromiseResult::Failed]);
+        let result = contract.resolve_collection_offer_fill(fill(), U64(0));
+        assert_eq!((result.charged.0, result.refunded.0), (0, 0));
+        assert_eq!(contract.get_collection_offer(U64(0)).unwrap().quantity, 2);
+        assert!(transfers().is_empty());
+
+        //once the offer is gone the unit is refunded
+        contract.internal_remove_collection_offer(0);
+        callback_env(vec![PromiseResult::Failed]);
+        contract.resolve_collection_offer_fill(fill(), U64(0));
+        assert_eq!(transfers(), vec![(account("alice"), ONE_NEAR)]);
+    }
+
+    #[test]
+    fn best_collection_offer_is_the_highest_that_can_be_filled() {
+        let mut contract = contract();
+        whitelist(&mut contract, NFT, None);
+        whitelist(&mut contract, "other", None);
+        testing_env!(context(OWNER).block_timestamp(100).build());
+        contract.internal_add_collection_offer(&collection_offer(0, 2 * ONE_NEAR, 1, None));
+        contract.internal_add_collection_offer(&collection_offer(1, 3 * ONE_NEAR, 1, Some(50)));
+        contract.internal_add_collection_offer(&collection_offer(2, ONE_NEAR, 1, None));
+
+        //the highest offer has expired
+        assert_eq!(contract.get_best_collection_offer(account(NFT)).unwrap().offer_id, U64(0));
+        contract.internal_remove_collection_offer(0);
+        assert_eq!(contract.get_best_collection_offer(account(NFT)).unwrap().offer_id, U64(2));
+
+        //collections without an offer are left out of the page
+        let best = contract.get_best_collection_offers(None, Some(2));
+        assert_eq!(best.len(), 1);
+        assert_eq!(best[0].0, account(NFT));
+        assert!(contract.get_best_collection_offers(Some(U128(2)), Some(2)).is_empty());
+    }
+
+    fn ft_purchase() -> Purchase {
+        Purchase { deposit: U128(12 * ONE_NEAR), ..purchase(false) }
+    }
+
+    //ft_transfer calls the market made, as (FT contract, receiver ID, amount)
+    fn ft_transfers() -> Vec<(String, String, String)> {
+        get_created_receipts()
+            .into_iter()
+            .flat_map(|receipt| {
+                let ft_token_id = receipt.receiver_id.to_string();
+                receipt.actions.into_iter().filter_map(move |action| match action {
+                    VmAction::FunctionCall { function_name, args, .. } if function_name == "ft_transfer" => {
+                        let args: near_sdk::serde_json::Value = near_sdk::serde_json::from_slice(&args).unwrap();
+                        Some((ft_token_id.clone(), args["receiver_id"].as_str()?.to_string(), args["amount"].as_str()?.to_string()))
+                    }
+                    _ => None,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    #[should_panic(expected = "This sale must be paid in Near")]
+    fn ft_payment_for_a_near_sale_is_rejected() {
+        let mut contract = contract();
+        contract.approved_ft_token_ids.insert(&account("usdc"));
+        whitelist(&mut contract, NFT, None);
+        list(&mut contract, SELLER, TOKEN, ONE_NEAR);
+
+        testing_env!(context("usdc").build());
+        let msg = format!(r#"{{"nft_contract_id": "{}", "token_id": "{}"}}"#, NFT, TOKEN);
+        contract.ft_on_transfer(account("buyer"), U128(ONE_NEAR), msg);
+    }
+
+    #[test]
+    fn ft_purchase_returns_the_unused_tokens() {
+        let mut contract = contract();
+        whitelist(&mut contract, NFT, None);
+
+        //what was sent above the price goes back through the ft_on_transfer return value
+        callback_env(vec![payout_result(&[(SELLER, 9 * ONE_NEAR), ("artist", 3 * ONE_NEAR / 4)])]);
+        assert_eq!(contract.resolve_ft_purchase(ft_purchase(), account("usdc"), false).0, 2 * ONE_NEAR);
+        let mut sent = ft_transfers();
+        sent.sort();
+        assert_eq!(
+            sent,
+            vec![
+                ("usdc".to_string(), "artist".to_string(), (3 * ONE_NEAR / 4).to_string()),
+                ("usdc".to_string(), SELLER.to_string(), (9 * ONE_NEAR).to_string()),
+                ("usdc".to_string(), "treasury".to_string(), (ONE_NEAR / 4).to_string()),
+            ]
+        );
+
+        //everything goes back if the card can't be transferred
+        callback_env(vec![PromiseResult::Failed]);
+        assert_eq!(contract.resolve_ft_purchase(ft_purchase(), account("usdc"), false).0, 12 * ONE_NEAR);
+        assert!(ft_transfers().is_empty());
+
+        //escrowed tokens aren't part of an ft_on_transfer call anymore, they're sent back instead
+        callback_env(vec![PromiseResult::Failed]);
+        assert_eq!(contract.resolve_ft_purchase(ft_purchase(), account("usdc"), true).0, 0);
+        assert_eq!(ft_transfers(), vec![("usdc".to_string(), "buyer".to_string(), (12 * ONE_NEAR).to_string())]);
+    }
+
+    //calls `method` as `caller_id` with 1 yoctoNEAR attached
+    fn call_as(caller_id: &str, method: impl FnOnce()) {
+        testing_env!(context(caller_id).attached_deposit(1).build());
+        method();
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn only_the_owner_proposes_an_owner() {
+        let mut contract = contract();
+        call_as("alice", || contract.propose_owner(account("alice")));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn only_the_owner_grants_roles() {
+        let mut contract = contract();
+        call_as("alice", || contract.grant_role(account("alice"), Role::FeeManager));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or a FeeManager can call this method")]
+    fn whitelist_manager_cannot_set_the_fee() {
+        let mut contract = contract();
+        call_as(OWNER, || contract.grant_role(account("alice"), Role::WhitelistManager));
+        call_as("alice", || contract.set_fee(100));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the proposed owner can accept the ownership")]
+    fn only_the_proposed_owner_accepts_the_ownership() {
+        let mut contract = contract();
+        call_as(OWNER, || contract.propose_owner(account("alice")));
+        call_as("bob", || contract.accept_ownership());
+    }
+
+    #[test]
+    fn proposed_owner_takes_over_once_accepted() {
+        let mut contract = contract();
+        call_as(OWNER, || contract.propose_owner(account("alice")));
+        //the ownership doesn't move until it is accepted
+        assert_eq!(contract.get_owner(), account(OWNER));
+        assert_eq!(contract.get_pending_owner(), Some(account("alice")));
+
+        call_as("alice", || contract.accept_ownership());
+        assert_eq!(contract.get_owner(), account("alice"));
+        assert_eq!(contract.get_pending_owner(), None);
+
+        //the new owner can use owner methods
+        call_as("alice", || contract.grant_role(account("bob"), Role::FeeManager));
+        call_as("bob", || contract.set_fee(100));
+        assert_eq!(contract.get_fee(), 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn previous_owner_loses_access() {
+        let mut contract = contract();
+        call_as(OWNER, || contract.propose_owner(account("alice")));
+        call_as("alice", || contract.accept_ownership());
+        call_as(OWNER, || contract.propose_owner(account(OWNER)));
+    }
+
+    //events logged since the last testing_env, as (event, data of its single entry). Every log has to be a NEP-297 event
+    fn events() -> Vec<(String, near_sdk::serde_json::Value)> {
+        get_logs()
+            .into_iter()
+            .map(|log| {
+                let event: near_sdk::serde_json::Value =
+                    near_sdk::serde_json::from_str(log.strip_prefix("EVENT_JSON:").expect("Not an event")).unwrap();
+                assert_eq!((event["standard"].as_str(), event["version"].as_str()), (Some("tenamint_market"), Some("1.0.0")));
+                let data = event["data"].as_array().unwrap();
+                assert_eq!(data.len(), 1);
+                (event["event"].as_str().unwrap().to_string(), data[0].clone())
+            })
+            .collect()
+    }
+
+    fn event_names() -> Vec<String> {
+        events().into_iter().map(|(event, _)| event).collect()
+    }
+
+    #[test]
+    fn events_are_logged_in_the_nep297_format() {
+        let mut contract = contract();
+        call_as(OWNER, || contract.set_fee(100));
+        assert_eq!(
+            get_logs(),
+            vec![r#"EVENT_JSON:{"standard":"tenamint_market","version":"1.0.0","event":"fee_update","data":[{"old_fee_bps":250,"new_fee_bps":100,"updated_by":"owner"}]}"#]
+        );
+    }
+
+    #[test]
+    fn admin_changes_are_logged() {
+        let mut contract = contract();
+        call_as(OWNER, || contract.grant_role(account("alice"), Role::FeeManager));
+        let (event, data) = events().remove(0);
+        assert_eq!((event.as_str(), &data["account_id"], &data["role"], &data["updated_by"]), ("role_grant", &"alice".into(), &"fee_manager".into(), &OWNER.into()));
+        call_as(OWNER, || contract.revoke_role(account("alice"), Role::FeeManager));
+        assert_eq!(event_names(), vec!["role_revoke"]);
+
+        call_as(OWNER, || contract.set_fee_recipient(account("vault")));
+        let (event, data) = events().remove(0);
+        assert_eq!((event.as_str(), &data["old_fee_recipient"], &data["new_fee_recipient"]), ("fee_recipient_update", &"treasury".into(), &"vault".into()));
+        call_as(OWNER, || contract.set_fee_tiers(vec![FeeTier { min_volume: U128(ONE_NEAR), fee_bps: 100 }]));
+        let (event, data) = events().remove(0);
+        assert_eq!((event.as_str(), &data["fee_tiers"][0]["min_volume"]), ("fee_tiers_update", &ONE_NEAR.to_string().into()));
+
+        call_as(OWNER, || contract.propose_owner(account("alice")));
+        let (event, data) = events().remove(0);
+        assert_eq!((event.as_str(), &data["old_owner_id"], &data["new_owner_id"]), ("ownership_proposal", &OWNER.into(), &"alice".into()));
+        call_as("alice", || contract.accept_ownership());
+        assert_eq!(event_names(), vec!["ownership_transfer"]);
+    }
+
+    #[test]
+    fn whitelist_and_currency_changes_are_logged() {
+        let mut contract = contract();
+        call_as(OWNER, || contract.allowlist_card(account(NFT), U128(ONE_NEAR), Some(100), None));
+        let (event, data) = events().remove(0);
+        assert_eq!((event.as_str(), &data["nft_contract_id"], &data["min_price_per_token"], &data["fee_bps"]), ("whitelist_add", &NFT.into(), &ONE_NEAR.to_string().into(), &100.into()));
+        call_as(OWNER, || contract.disallow_card(account(NFT)));
+        let (event, data) = events().remove(0);
+        assert_eq!((event.as_str(), &data["min_price_per_token"]), ("whitelist_remove", &near_sdk::serde_json::Value::Null));
+
+        call_as(OWNER, || contract.allowlist_ft(account("usdc")));
+        let (event, data) = events().remove(0);
+        assert_eq!((event.as_str(), &data["ft_token_id"]), ("currency_add", &"usdc".into()));
+        call_as(OWNER, || contract.disallow_ft(account("usdc")));
+        assert_eq!(event_names(), vec!["currency_remove"]);
+    }
+
+    #[test]
+    fn listings_are_logged() {
+        let mut contract = contract();
+        whitelist(&mut contract, NFT, None);
+        call_as(SELLER, || list(&mut contract, SELLER, TOKEN, ONE_NEAR));
+        let (event, data) = events().remove(0);
+        assert_eq!((event.as_str(), &data["token_id"], &data["price"], &data["sale_type"]), ("listing_create", &TOKEN.into(), &ONE_NEAR.to_string().into(), &"fixed_price".into()));
+
+        call_as(SELLER, || contract.update_price(account(NFT), TOKEN.to_string(), U128(2 * ONE_NEAR), None));
+        let (event, data) = events().remove(0);
+        assert_eq!((event.as_str(), &data["price"]), ("listing_update", &(2 * ONE_NEAR).to_string().into()));
+
+        call_as(SELLER, || contract.unlist(account(NFT), TOKEN.to_string()));
+        let (event, data) = events().remove(0);
+        assert_eq!((event.as_str(), &data["owner_id"]), ("listing_remove", &SELLER.into()));
+    }
+
+    #[test]
+    fn purchases_are_logged() {
+        let mut contract = contract();
+        whitelist(&mut contract, NFT, None);
+        callback_env(vec![payout_result(&[(SELLER, 9 * ONE_NEAR), ("artist", 3 * ONE_NEAR / 4)])]);
+        contract.resolve_purchase(purchase(false));
+        let settled = events();
+        assert_eq!(settled.iter().map(|(event, _)| event.as_str()).collect::<Vec<_>>(), vec!["purchase_settle", "fee_paid"]);
+        assert_eq!((&settled[0].1["charged"], &settled[0].1["refunded"]), (&(10 * ONE_NEAR).to_string().into(), &(2 * ONE_NEAR).to_string().into()));
+        assert_eq!((&settled[1].1["fee_recipient"], &settled[1].1["amount"]), (&"treasury".into(), &(ONE_NEAR / 4).to_string().into()));
+
+        callback_env(vec![PromiseResult::Failed]);
+        contract.resolve_purchase(purchase(false));
+        let (event, data) = events().remove(0);
+        assert_eq!((event.as_str(), &data["charged"], &data["refunded"]), ("purchase_refund", &"0".into(), &(12 * ONE_NEAR).to_string().into()));
+    }
+
+    #[test]
+    fn late_bid_extends_auction() {
+        let mut contract = contract();
+        let end_at = 100 * AUCTION_EXTENSION;
+        let now = end_at - AUCTION_EXTENSION / 2;
+        testing_env!(context("alice").block_timestamp(now).build());
+        contract.internal_place_bid(&contract_and_token_id(), sale(ONE_NEAR, auction(end_at)), account("alice"), ONE_NEAR);
+
+        assert_eq!(highest_bid(&contract).1, now + AUCTION_EXTENSION);
+    }
+}
+
@@ -6,7 +6,29 @@ use crate::*;
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct SaleArgs {
-    pub sale_conditions: SalePriceInYoctoNear,
+    //the price is still accepted under its former `sale_conditions` name
+    #[serde(alias = "sale_conditions")]
+    pub price: U128,
+    //defaults to NEAR
+    pub currency: Option<Currency>,
+    //lists the token as an english auction with the price as reserve price
+    pub auction: Option<AuctionArgs>,
+    //lists the token as a dutch auction with the price as start price
+    pub dutch_auction: Option<DutchAuctionArgs>,
+    //the listing can't be bought anymore after this time (nanoseconds)
+    pub expires_at: Option<U64>,
+}
+
+//what the owner approved the market for: listing the token, or selling it to an offer or a collection offer
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(untagged)]
+pub enum ApproveArgs {
+    //{"accept_offer": "<buyer>"}
+    AcceptOffer { accept_offer: AccountId },
+    //{"fill_collection_offer": "<offer ID>"}
+    FillCollectionOffer { fill_collection_offer: U64 },
+    Sale(SaleArgs),
 }
 
 /*
@@ -55,42 +77,175 @@ impl NonFungibleTokenApprovalsReceiver for Contract {
             "owner_id should be signer_id"
         );
 
+        //the approval is either a listing or the owner accepting an offer. The market assumes that the user passed
+        //in a proper msg. If they didn't, it panics. 
+        let sale_args = match near_sdk::serde_json::from_str(&msg).expect("Not valid SaleArgs") {
+            ApproveArgs::AcceptOffer { accept_offer } => {
+                self.internal_accept_offer(nft_contract_id, token_id, owner_id, approval_id, accept_offer);
+                return;
+            }
+            ApproveArgs::FillCollectionOffer { fill_collection_offer } => {
+                self.internal_fill_collection_offer(nft_contract_id, token_id, owner_id, approval_id, fill_collection_offer.0);
+                return;
+            }
+            ApproveArgs::Sale(sale_args) => sale_args,
+        };
+
+        self.internal_list_sale(nft_contract_id, token_id, owner_id, approval_id, sale_args, false);
+    }
+}
+
+/*
+    trait that will be used as the callback from the NFT contract. When nft_transfer_call is
+    called with the market as receiver, the token is now held by the market and this is the function
+    that is invoked.
+*/
+trait NonFungibleTokenReceiver {
+    fn nft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_id: AccountId,
+        token_id: TokenId,
+        msg: String,
+    ) -> PromiseOrValue<bool>;
+}
+
+#[near_bindgen]
+impl NonFungibleTokenReceiver for Contract {
+    //lists a token the owner hands over to the market. The sale can't fail because of a revoked approval or a transfer
+    //since the market holds the token until it's sold or unlisted. The msg is the same as for nft_approve. If the listing
+    //is rejected, the token goes back to the owner
+    fn nft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_id: AccountId,
+        token_id: TokenId,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        // get the contract ID which is the predecessor
+        let nft_contract_id = env::predecessor_account_id();
+        //make sure that the signer isn't the predecessor. This is so that we're sure
+        //this was called via a cross-contract call
+        assert_ne!(
+            nft_contract_id,
+            env::signer_account_id(),
+            "nft_on_transfer should only be called via cross-contract call"
+        );
+        //only the owner can hand their token over, an approved account would list it for them
+        assert_eq!(sender_id, previous_owner_id, "Only the owner can transfer a token to the market");
+
+        let sale_args: SaleArgs = near_sdk::serde_json::from_str(&msg).expect("Not valid SaleArgs");
+        //the market transfers the token itself, so there is no approval ID
+        self.internal_list_sale(nft_contract_id, token_id, previous_owner_id, 0, sale_args, true);
+
+        //keep the token
+        PromiseOrValue::Value(false)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    //called by nft contracts that let the approved accounts know when an approval is revoked. The market can't transfer
+    //the token anymore, so its sale is taken down and the bid on it (if it's an auction) is refunded
+    pub fn nft_on_revoke(&mut self, token_id: TokenId) {
+        // get the contract ID which is the predecessor
+        let nft_contract_id = env::predecessor_account_id();
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+
+        if self.sales.get(&contract_and_token_id).is_some() {
+            self.internal_drop_sale(nft_contract_id, token_id);
+        }
+    }
+}
+
+impl Contract {
+    //lists a token the market was approved for. Every listing goes through here, whether it came from nft_approve or list
+    pub(crate) fn internal_list_sale(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        owner_id: AccountId,
+        approval_id: u64,
+        sale_args: SaleArgs,
+        custodial: bool,
+    ) {
         //we need to enforce that the user has enough storage for 1 EXTRA sale.  
 
         //get the storage for a sale. dot 0 converts from U128 to u128
         let storage_amount = self.storage_minimum_balance().0;
         //get the total storage paid by the owner
-        let owner_paid_storage = self.storage_deposits.get(&signer_id).unwrap_or(0);
-        //get the storage required which is simply the storage for the number of sales they have + 1 
-        let signer_storage_required = (self.get_supply_by_owner_id(signer_id).0 + 1) as u128 * storage_amount;
+        let owner_paid_storage = self.storage_deposits.get(&owner_id).unwrap_or(0);
+        //get the storage required which is simply the storage for the number of sales and offers they have + 1 
+        let owner_storage_required = (self.internal_storage_entries(&owner_id) + 1) as u128 * storage_amount;
         
         //make sure that the total paid is >= the required storage
         assert!(
-            owner_paid_storage >= signer_storage_required,
+            owner_paid_storage >= owner_storage_required,
             "Insufficient storage paid: {}, for {} sales at {} rate of per sale",
-            owner_paid_storage, signer_storage_required / STORAGE_PER_SALE, STORAGE_PER_SALE
+            owner_paid_storage, owner_storage_required / STORAGE_PER_SALE, STORAGE_PER_SALE
         );
 
         //if all these checks pass we can create the sale conditions object.
-        let SaleArgs { sale_conditions } =
-            //the sale conditions come from the msg field. The market assumes that the user passed
-            //in a proper msg. If they didn't, it panics. 
-            near_sdk::serde_json::from_str(&msg).expect("Not valid SaleArgs");
+        let SaleArgs { price, currency, auction, dutch_auction, expires_at } = sale_args;
+
+        //an auction runs until its end time, other listings can expire
+        if let Some(expires_at) = expires_at {
+            assert!(expires_at.0 > env::block_timestamp(), "Sale must expire in the future");
+            assert!(auction.is_none(), "Auctions end at end_at and can't expire");
+        }
+
+        //make sure the market accepts the currency the sale is listed in
+        let currency = currency.unwrap_or(Currency::Near);
+        self.assert_approved_currency(&currency);
+
+        //only whitelisted TokenizedCards can be listed, at least at their min price in that currency. The price of a
+        //dutch auction goes down to its floor price, so that's the one that has to be above the min price
+        let lowest_price = dutch_auction.as_ref().map_or(price, |dutch_auction| dutch_auction.floor_price);
+        self.assert_listing_allowed(&nft_contract_id, lowest_price, &currency);
+
+        //a fixed price sale unless auction parameters were passed in
+        let sale_type = match (auction, dutch_auction) {
+            (Some(auction), None) => SaleType::Auction(auction.into()),
+            (None, Some(dutch_auction)) => SaleType::DutchAuction(dutch_auction.into_dutch_auction(price)),
+            (None, None) => SaleType::FixedPrice,
+            _ => env::panic_str("A sale can't be both an auction and a dutch auction"),
+        };
 
         //create the unique sale ID which is the contract + DELIMITER + token ID
         let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+
+        //re-approving replaces the sale, which can't happen while bids are escrowed on it. If the token changed hands
+        //since it was listed, the sale of the previous owner is taken down first
+        if let Some(sale) = self.sales.get(&contract_and_token_id) {
+            assert!(!sale.has_bids(), "Cannot relist an auction with bids");
+            if sale.owner_id != owner_id {
+                self.internal_remove_sale(nft_contract_id.clone(), token_id.clone());
+            }
+        }
         
-        //insert the key value pair into the sales map. Key is the unique ID. value is the sale object
-        self.sales.insert(
-            &contract_and_token_id,
-            &Sale {
-                owner_id: owner_id.clone(), //owner of the sale / token
-                approval_id, //approval ID for that token that was given to the market
-                nft_contract_id: nft_contract_id.to_string(), //NFT contract the token was minted on
-                token_id: token_id.clone(), //the actual token ID
-                sale_conditions, //the sale conditions 
-           },
-        );
+        let sale = Sale {
+            owner_id: owner_id.clone(), //owner of the sale / token
+            approval_id, //approval ID for that token that was given to the market
+            nft_contract_id: nft_contract_id.to_string(), //NFT contract the token was minted on
+            token_id: token_id.clone(), //the actual token ID
+            price, //the sale price
+            currency, //the currency the price is in
+            sale_type, //fixed price or auction
+            expires_at, //when the listing expires
+            custodial, //whether the market holds the token
+        };
+
+        //insert the key value pair into the sales map. Key is the unique ID. value is the sale object.
+        //re-approving a token that is already listed updates its sale
+        let previous_sale = self.sales.insert(&contract_and_token_id, &sale);
+        if let Some(previous_sale) = &previous_sale {
+            self.internal_remove_from_order_book(previous_sale);
+        }
+        self.internal_add_to_order_book(&sale);
+        match previous_sale {
+            Some(_) => MarketEvent::ListingUpdate([(&sale).into()]).emit(),
+            None => MarketEvent::ListingCreate([(&sale).into()]).emit(),
+        }
 
         //Extra functionality that populates collections necessary for the view calls 
 
@@ -0,0 +1,326 @@
+use crate::*;
+
+//an offer on a token, listed or not. The amount is escrowed by the market until the offer is accepted or cancelled
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Offer {
+    //account that made the offer and receives the token
+    pub buyer_id: AccountId,
+    //nft contract where the token was minted
+    pub nft_contract_id: AccountId,
+    //token the offer is for
+    pub token_id: TokenId,
+    //amount of NEAR offered
+    pub amount: U128,
+    //the offer can't be accepted anymore after this time (nanoseconds)
+    pub expires_at: Option<U64>,
+}
+
+impl Offer {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| env::block_timestamp() >= expires_at.0)
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    //offers the attached NEAR for a token. The deposit has to match the amount and is held by the market.
+    //making a new offer on the same token replaces the previous one, which is refunded
+    #[payable]
+    pub fn make_offer(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        amount: U128,
+        expires_at: Option<U64>,
+    ) {
+        let buyer_id = env::predecessor_account_id();
+        self.assert_whitelisted(&nft_contract_id);
+        assert!(amount.0 > 0, "Offer amount must be greater than 0");
+        assert_eq!(env::attached_deposit(), amount.0, "Attached deposit must be equal to the offer amount");
+        if let Some(expires_at) = expires_at {
+            assert!(expires_at.0 > env::block_timestamp(), "Offer must expire in the future");
+        }
+
+        //get the unique sale ID (contract + DELIMITER + token ID)
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+
+        //make sure the buyer isn't offering on their own listing
+        if let Some(sale) = self.sales.get(&contract_and_token_id) {
+            assert_ne!(sale.owner_id, buyer_id, "Cannot make an offer on your own sale.");
+        }
+
+        //refund the offer that is being replaced
+        if let Some(previous) = self.internal_remove_offer(&contract_and_token_id, &buyer_id) {
+            Promise::new(buyer_id.clone()).transfer(previous.amount.0);
+        }
+
+        //offers take up storage just like sales, so the buyer needs enough storage for 1 EXTRA entry
+        let buyer_paid_storage = self.storage_deposits.get(&buyer_id).unwrap_or(0);
+        let buyer_storage_required = self.internal_storage_entries(&buyer_id) as u128 * STORAGE_PER_SALE + STORAGE_PER_SALE;
+        assert!(
+            buyer_paid_storage >= buyer_storage_required,
+            "Insufficient storage paid: {}, for {} sales and offers at {} rate of per sale",
+            buyer_paid_storage, buyer_storage_required / STORAGE_PER_SALE, STORAGE_PER_SALE
+        );
+
+        self.internal_add_offer(
+            &contract_and_token_id,
+            Offer {
+                buyer_id,
+                nft_contract_id,
+                token_id,
+                amount,
+                expires_at,
+            },
+        );
+    }
+
+    //withdraws an offer and refunds the escrowed amount. Anyone can clear an offer once it has expired
+    #[payable]
+    pub fn cancel_offer(&mut self, nft_contract_id: AccountId, token_id: TokenId, buyer_id: Option<AccountId>) {
+        //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
+        assert_one_yocto();
+        let caller_id = env::predecessor_account_id();
+        let buyer_id = buyer_id.unwrap_or_else(|| caller_id.clone());
+
+        //get the unique sale ID (contract + DELIMITER + token ID)
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        let offer = self.internal_remove_offer(&contract_and_token_id, &buyer_id).expect("No offer");
+        assert!(caller_id == buyer_id || offer.is_expired(), "Only the buyer can cancel an offer before it expires");
+
+        Promise::new(buyer_id).transfer(offer.amount.0);
+    }
+
+    //views
+
+    //get the offer a buyer made on a given unique sale ID (contract + DELIMITER + token ID)
+    pub fn get_offer(&self, nft_contract_token: ContractAndTokenId, buyer_id: AccountId) -> Option<Offer> {
+        self.offers_by_token
+            .get(&nft_contract_token)
+            .and_then(|offers| offers.get(&buyer_id))
+    }
+
+    //returns paginated offers made on a given unique sale ID, expired ones included
+    pub fn get_offers_by_token(
+        &self,
+        nft_contract_token: ContractAndTokenId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Offer> {
+        let offers = if let Some(offers) = self.offers_by_token.get(&nft_contract_token) {
+            offers
+        } else {
+            return vec![];
+        };
+
+        //where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        offers
+            .values()
+            .skip(start as usize)
+            .take(limit.unwrap_or(0) as usize)
+            .collect()
+    }
+
+    //returns the number of offers a buyer has up
+    pub fn get_supply_offers_by_buyer_id(&self, account_id: AccountId) -> U64 {
+        U64(self.offers_by_buyer_id.get(&account_id).map(|offers| offers.len()).unwrap_or(0))
+    }
+
+    //returns paginated offers made by a given buyer
+    pub fn get_offers_by_buyer_id(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Offer> {
+        let offers = if let Some(offers) = self.offers_by_buyer_id.get(&account_id) {
+            offers
+        } else {
+            return vec![];
+        };
+
+        //where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        offers
+            .as_vector()
+            .iter()
+            .skip(start as usize)
+            .take(limit.unwrap_or(0) as usize)
+            .filter_map(|contract_and_token_id| self.get_offer(contract_and_token_id, account_id.clone()))
+            .collect()
+    }
+}
+
+//transfers a token sold to an offer to its buyer, with the approval that was just given
+pub(crate) fn internal_transfer_payout(purchase: &Purchase, approval_id: u64) -> Promise {
+    ext_contract::ext(purchase.nft_contract_id.clone())
+        .with_attached_deposit(1)
+        .with_static_gas(GAS_FOR_NFT_TRANSFER)
+        .nft_transfer_payout(
+            purchase.buyer_id.clone(),
+            purchase.token_id.clone(),
+            approval_id,
+            "payout from market".to_string(),
+            purchase.net_price(),
+            10,
+        )
+}
+
+impl Contract {
+    //sells the token to the buyer of the offer. Called from nft_on_approve once the owner approved the market
+    pub(crate) fn internal_accept_offer(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        owner_id: AccountId,
+        approval_id: u64,
+        buyer_id: AccountId,
+    ) -> Promise {
+        //get the unique sale ID (contract + DELIMITER + token ID)
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        let offer = self.internal_remove_offer(&contract_and_token_id, &buyer_id).expect("No offer");
+        assert!(!offer.is_expired(), "The offer has expired");
+        assert_ne!(owner_id, buyer_id, "Cannot accept your own offer.");
+        self.assert_whitelisted(&nft_contract_id);
+
+        let purchase = self.internal_sell_approved_token(nft_contract_id, token_id, owner_id, buyer_id, offer.amount);
+        internal_transfer_payout(&purchase, approval_id).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_PURCHASE)
+                .resolve_purchase(purchase),
+        )
+    }
+
+    //prepares the sale of a token the owner just approved the market for to a buyer whose NEAR is escrowed by the market.
+    //The callers transfer it with `internal_transfer_payout`, and their callback pays out the amount or refunds the buyer
+    //if the transfer fails
+    pub(crate) fn internal_sell_approved_token(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        owner_id: AccountId,
+        buyer_id: AccountId,
+        amount: U128,
+    ) -> Purchase {
+        //a listing of the token is taken down, unless bids are escrowed on it
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        if let Some(sale) = self.sales.get(&contract_and_token_id) {
+            assert!(!sale.has_bids(), "Cannot sell a token that is in an auction with bids");
+            self.internal_remove_sale(nft_contract_id.clone(), token_id.clone());
+        }
+
+        //the fee is taken from the gross amount, the rest is what the nft contract splits into the payout
+        let fee = U128(self.internal_fee_amount(&owner_id, &nft_contract_id, amount.0));
+        Purchase {
+            nft_contract_id,
+            token_id,
+            seller_id: owner_id,
+            buyer_id,
+            price: amount,
+            fee,
+            deposit: amount,
+            custodial: false,
+        }
+    }
+
+    //number of sales, bundles and offers an account pays storage for
+    pub(crate) fn internal_storage_entries(&self, account_id: &AccountId) -> u64 {
+        self.get_supply_by_owner_id(account_id.clone()).0
+            + self.get_supply_bundles_by_owner_id(account_id.clone()).0
+            + self.get_supply_offers_by_buyer_id(account_id.clone()).0
+            + self.get_supply_collection_offers_by_bidder_id(account_id.clone()).0
+    }
+
+    //adds an offer to the offer collections
+    pub(crate) fn internal_add_offer(&mut self, contract_and_token_id: &ContractAndTokenId, offer: Offer) {
+        //get the offers on the token. If there are none, we create a new empty map
+        let mut offers = self.offers_by_token.get(contract_and_token_id).unwrap_or_else(|| {
+            UnorderedMap::new(
+                StorageKey::OffersByTokenInner {
+                    //we get a new unique prefix for the collection by hashing the unique sale ID
+                    token_hash: hash_sale_id(contract_and_token_id),
+                }
+                .try_to_vec()
+                .unwrap(),
+            )
+        });
+        offers.insert(&offer.buyer_id, &offer);
+        self.offers_by_token.insert(contract_and_token_id, &offers);
+
+        //get the offered token IDs of the nft contract. If there are none, we create a new empty set
+        let mut by_nft_contract_id = self
+            .offered_token_ids_by_nft_contract_id
+            .get(&offer.nft_contract_id)
+            .unwrap_or_else(|| {
+                UnorderedSet::new(
+                    StorageKey::OfferedTokenIdsByNFTContractIdInner {
+                        //we get a new unique prefix for the collection by hashing the nft contract
+                        account_id_hash: hash_account_id(&offer.nft_contract_id),
+                    }
+                    .try_to_vec()
+                    .unwrap(),
+                )
+            });
+        by_nft_contract_id.insert(&offer.token_id);
+        self.offered_token_ids_by_nft_contract_id
+            .insert(&offer.nft_contract_id, &by_nft_contract_id);
+
+        //get the offers of the buyer. If there are none, we create a new empty set
+        let mut by_buyer_id = self.offers_by_buyer_id.get(&offer.buyer_id).unwrap_or_else(|| {
+            UnorderedSet::new(
+                StorageKey::OffersByBuyerIdInner {
+                    //we get a new unique prefix for the collection by hashing the buyer
+                    account_id_hash: hash_account_id(&offer.buyer_id),
+                }
+                .try_to_vec()
+                .unwrap(),
+            )
+        });
+        by_buyer_id.insert(contract_and_token_id);
+        self.offers_by_buyer_id.insert(&offer.buyer_id, &by_buyer_id);
+    }
+
+    //removes an offer from the offer collections and returns it, if there was one
+    pub(crate) fn internal_remove_offer(
+        &mut self,
+        contract_and_token_id: &ContractAndTokenId,
+        buyer_id: &AccountId,
+    ) -> Option<Offer> {
+        let mut offers = self.offers_by_token.get(contract_and_token_id)?;
+        let offer = offers.remove(buyer_id)?;
+
+        //if the map is now empty we remove it, and the token from the offered tokens of its nft contract.
+        //otherwise we insert it back
+        if offers.is_empty() {
+            self.offers_by_token.remove(contract_and_token_id);
+
+            let mut by_nft_contract_id = self
+                .offered_token_ids_by_nft_contract_id
+                .get(&offer.nft_contract_id)
+                .expect("No offer by_nft_contract_id");
+            by_nft_contract_id.remove(&offer.token_id);
+            if by_nft_contract_id.is_empty() {
+                self.offered_token_ids_by_nft_contract_id.remove(&offer.nft_contract_id);
+            } else {
+                self.offered_token_ids_by_nft_contract_id
+                    .insert(&offer.nft_contract_id, &by_nft_contract_id);
+            }
+        } else {
+            self.offers_by_token.insert(contract_and_token_id, &offers);
+        }
+
+        let mut by_buyer_id = self.offers_by_buyer_id.get(buyer_id).expect("No offer by_buyer_id");
+        by_buyer_id.remove(contract_and_token_id);
+        if by_buyer_id.is_empty() {
+            self.offers_by_buyer_id.remove(buyer_id);
+        } else {
+            self.offers_by_buyer_id.insert(buyer_id, &by_buyer_id);
+        }
+
+        Some(offer)
+    }
+}
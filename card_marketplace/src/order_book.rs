@@ -0,0 +1,110 @@
+use crate::*;
+
+//fixed price sales of a collection in one currency, sorted by price then token ID. Auctions don't have a price to sort
+//by until they end, and the price of a dutch auction changes on its own, so only fixed price sales are in the order book
+pub type OrderBook = TreeMap<(Balance, TokenId), ()>;
+
+#[near_bindgen]
+impl Contract {
+    //views
+
+    //lowest price a card of the collection is listed for at a fixed price, in NEAR unless a currency is passed in
+    pub fn get_floor_price(&self, nft_contract_id: AccountId, currency: Option<Currency>) -> Option<U128> {
+        self.internal_sorted_sales(nft_contract_id, currency.unwrap_or(Currency::Near), None, None)
+            .next()
+            .map(|sale| sale.price)
+    }
+
+    //returns paginated fixed price sales of a collection from the cheapest to the most expensive, with a price between
+    //`min_price` and `max_price` (both included) in NEAR unless a currency is passed in
+    pub fn get_sales_by_price(
+        &self,
+        nft_contract_id: AccountId,
+        min_price: Option<U128>,
+        max_price: Option<U128>,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+        currency: Option<Currency>,
+    ) -> Vec<Sale> {
+        //where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        self.internal_sorted_sales(
+            nft_contract_id,
+            currency.unwrap_or(Currency::Near),
+            min_price.map(|price| price.0),
+            max_price.map(|price| price.0),
+        )
+        .skip(start as usize)
+        .take(limit.unwrap_or(0) as usize)
+        .collect()
+    }
+}
+
+impl Contract {
+    //fixed price sales of a collection in the given currency from the cheapest to the most expensive, leaving out the expired ones
+    pub(crate) fn internal_sorted_sales(
+        &self,
+        nft_contract_id: AccountId,
+        currency: Currency,
+        min_price: Option<Balance>,
+        max_price: Option<Balance>,
+    ) -> impl Iterator<Item = Sale> + '_ {
+        let order_book = self.sales_by_price.get(&(nft_contract_id.clone(), currency));
+
+        //the empty token ID sorts before every other one, so the walk starts at the first sale at the min price. Every
+        //next key is only read when it is needed, so pagination stops reading the order book at the limit
+        let first = order_book
+            .as_ref()
+            .and_then(|order_book| order_book.ceil_key(&(min_price.unwrap_or(0), TokenId::new())));
+
+        std::iter::successors(first, move |key| order_book.as_ref().and_then(|order_book| order_book.higher(key)))
+            .take_while(move |(price, _)| max_price.is_none_or(|max_price| *price <= max_price))
+            .map(move |(_, token_id)| {
+                let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+                self.sales.get(&contract_and_token_id).expect("No sale")
+            })
+            //expired sales can't be bought anymore so they're left out
+            .filter(|sale| !sale.is_expired())
+    }
+
+    //adds a sale to the order book of its collection if it is sold at a fixed price
+    pub(crate) fn internal_add_to_order_book(&mut self, sale: &Sale) {
+        if !matches!(sale.sale_type, SaleType::FixedPrice) {
+            return;
+        }
+
+        let key = (sale.nft_contract_id.parse().unwrap(), sale.currency.clone());
+        //get the order book of the collection in the sale's currency. If there is none, we create a new empty one
+        let mut order_book = self.sales_by_price.get(&key).unwrap_or_else(|| {
+            TreeMap::new(
+                StorageKey::SalesByPriceInner {
+                    //we get a new unique prefix for the order book by hashing the collection and currency
+                    order_book_hash: hash_order_book_id(&key),
+                }
+                .try_to_vec()
+                .unwrap(),
+            )
+        });
+        order_book.insert(&(sale.price.0, sale.token_id.clone()), &());
+        self.sales_by_price.insert(&key, &order_book);
+    }
+
+    //removes a sale from the order book of its collection, if it was in it
+    pub(crate) fn internal_remove_from_order_book(&mut self, sale: &Sale) {
+        let key = (sale.nft_contract_id.parse().unwrap(), sale.currency.clone());
+        let mut order_book = if let Some(order_book) = self.sales_by_price.get(&key) {
+            order_book
+        } else {
+            return;
+        };
+
+        order_book.remove(&(sale.price.0, sale.token_id.clone()));
+        //drop the order book once the collection has no fixed price sales left in that currency
+        if order_book.is_empty() {
+            self.sales_by_price.remove(&key);
+        } else {
+            self.sales_by_price.insert(&key, &order_book);
+        }
+    }
+}
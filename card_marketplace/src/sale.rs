@@ -1,5 +1,26 @@
 use crate::*;
-use near_sdk::promise_result_as_success;
+
+//currency a sale is listed and paid in. Serialized as "near" or {"fungible_token": "<ft contract>"}
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum Currency {
+  Near,
+  FungibleToken(FungibleTokenId),
+}
+
+//how a sale is sold. Serialized as "fixed_price" or {"auction": {...}}
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum SaleType {
+  //bought as soon as someone pays the price
+  FixedPrice,
+  //english auction where the price is the reserve price
+  Auction(Auction),
+  //declining price where the price is the start price
+  DutchAuction(DutchAuction),
+}
 
 //struct that holds important information about each sale on the market
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
@@ -7,14 +28,122 @@ use near_sdk::promise_result_as_success;
 pub struct Sale {
   //owner of the sale
   pub owner_id: AccountId,
-  //market contract's approval ID to transfer the token on behalf of the owner
+  //market contract's approval ID to transfer the token on behalf of the owner. Not used when the market holds the token
   pub approval_id: u64,
   //nft contract where the token was minted
   pub nft_contract_id: String,
   //actual token ID for sale
   pub token_id: String,
-  //sale price in yoctoNEAR that the token is listed for
-  pub sale_conditions: SalePriceInYoctoNear,
+  //sale price that the token is listed for, in the smallest unit of the currency
+  pub price: U128,
+  //currency the price is in
+  pub currency: Currency,
+  //fixed price or auction
+  pub sale_type: SaleType,
+  //the sale can't be bought anymore after this time (nanoseconds) and can be pruned by anyone
+  pub expires_at: Option<U64>,
+  //the owner transferred the token to the market with nft_transfer_call. It's given back when the sale is taken down
+  pub custodial: bool,
+}
+
+impl Sale {
+  //price a buyer pays right now. Only dutch auctions change price over time
+  pub fn current_price(&self) -> Balance {
+    match &self.sale_type {
+      SaleType::DutchAuction(dutch_auction) => dutch_auction.price_at(self.price.0, env::block_timestamp()),
+      _ => self.price.0,
+    }
+  }
+
+  pub fn is_expired(&self) -> bool {
+    self.expires_at.is_some_and(|expires_at| env::block_timestamp() >= expires_at.0)
+  }
+
+  //whether the sale is an auction that someone has already bid on
+  pub fn has_bids(&self) -> bool {
+    matches!(&self.sale_type, SaleType::Auction(auction) if auction.highest_bid.is_some())
+  }
+}
+
+//everything the purchase callbacks need to settle a sale
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Purchase {
+  pub nft_contract_id: AccountId,
+  pub token_id: TokenId,
+  pub seller_id: AccountId,
+  pub buyer_id: AccountId,
+  //price the token is sold for
+  pub price: U128,
+  //part of the price that goes to the fee recipient
+  pub fee: U128,
+  //what the buyer paid. Anything above the price is refunded
+  pub deposit: U128,
+  //the token is transferred out of the market's custody
+  pub custodial: bool,
+}
+
+//amounts a purchase ended up taking from the buyer and giving back
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseResult {
+  pub charged: U128,
+  pub refunded: U128,
+}
+
+//outcome of a `prune_expired` call
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PruneResult {
+  pub pruned: U64,
+  //where to start the next call. Every sale has been checked once it is past the number of sales
+  pub next_index: U64,
+}
+
+impl Purchase {
+  //what the nft contract splits into the payout
+  pub fn net_price(&self) -> U128 {
+    U128(self.price.0 - self.fee.0)
+  }
+
+}
+
+impl Contract {
+  //logs the outcome of a purchase, and the fee if it went through. Nothing is charged if the whole deposit was refunded
+  pub(crate) fn internal_emit_purchase(&self, purchase: &Purchase, currency: Currency, refunded: Balance) -> PurchaseResult {
+    let charged = purchase.deposit.0 - refunded;
+    let log = PurchaseLog {
+      nft_contract_id: purchase.nft_contract_id.clone(),
+      token_id: purchase.token_id.clone(),
+      seller_id: purchase.seller_id.clone(),
+      buyer_id: purchase.buyer_id.clone(),
+      currency: currency.clone(),
+      price: purchase.price,
+      fee: purchase.fee,
+      charged: U128(charged),
+      refunded: U128(refunded),
+    };
+    if charged > 0 {
+      MarketEvent::PurchaseSettle([log]).emit();
+      if purchase.fee.0 > 0 {
+        MarketEvent::FeePaid([FeeLog {
+          nft_contract_id: purchase.nft_contract_id.clone(),
+          token_id: purchase.token_id.clone(),
+          seller_id: purchase.seller_id.clone(),
+          fee_recipient: self.fee_recipient.clone(),
+          currency,
+          amount: purchase.fee,
+        }]).emit();
+      }
+    } else {
+      MarketEvent::PurchaseRefund([log]).emit();
+    }
+
+    PurchaseResult {
+      charged: U128(charged),
+      refunded: U128(refunded),
+    }
+  }
 }
 
 #[near_bindgen]
@@ -25,43 +154,57 @@ impl Contract {
   pub fn unlist(&mut self, nft_contract_id: AccountId, token_id: String) {
     //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
     assert_one_yocto();
-    //get the sale object as the return value from removing the sale internally
-    let sale = self.internal_remove_sale(nft_contract_id.into(), token_id);
+    //get the sale object as the return value from removing the sale internally. A token held by the market is given back
+    let sale = self.internal_drop_sale(nft_contract_id.into(), token_id);
     //get the predecessor of the call and make sure they're the owner of the sale
     let owner_id = env::predecessor_account_id();
     //if this fails, the remove sale will revert
     assert_eq!(owner_id, sale.owner_id, "Must be sale owner");
+    //bidders' deposits are escrowed, so an auction can't be pulled once someone has bid
+    assert!(!sale.has_bids(), "Cannot unlist an auction with bids");
   }
 
-  //updates the price for a sale on the market
+  //lists a token the market was approved for without a msg. The nft contract is asked for the token to check that the
+  //caller owns it and to get the market's approval ID, then the sale goes through the same checks as nft_approve
   #[payable]
-  pub fn list(&mut self, nft_contract_id: AccountId, token_id: String, price: U128) {
+  pub fn list(&mut self, nft_contract_id: AccountId, token_id: String, price: U128, currency: Option<Currency>) -> Promise {
     //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
     assert_one_yocto();
 
-    //get the min price from the whitelist. If there is no result, panic. 
-    let min_price = self.whitelist.get(&nft_contract_id).expect("No Whitelist");
-
-    //make sure the setting price is greater than the min price
-    assert!(price >= min_price, "Setted price must be greater than or equal to the min price: {:?}", min_price);
-
-    //get the ID from the nft contract and token
-    let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
-    //create new Sate item
-    let sale = Sale {
-      owner_id: env::predecessor_account_id(),
-      approval_id: self.sales.len() + 1,
-      nft_contract_id: nft_contract_id.into(),
-      token_id: token_id,
-      sale_conditions: price,
+    let sale_args = SaleArgs {
+      price,
+      currency,
+      auction: None,
+      dutch_auction: None,
+      expires_at: None,
     };
-    //inseret new item to sales list
-    self.sales.insert(&contract_and_token_id, &sale);
+
+    ext_nft_contract::ext(nft_contract_id.clone())
+      .with_static_gas(GAS_FOR_NFT_TOKEN)
+      .nft_token(token_id.clone())
+      .then(
+        Self::ext(env::current_account_id())
+          .with_static_gas(GAS_FOR_RESOLVE_LIST)
+          .resolve_list(nft_contract_id, token_id, env::predecessor_account_id(), sale_args),
+      )
   }
-  
-  //updates the price for a sale on the market
+
+  //private method used to resolve the nft_token call of list. Lists the token if the caller owns it and the market is approved
+  #[private]
+  pub fn resolve_list(&mut self, nft_contract_id: AccountId, token_id: String, owner_id: AccountId, sale_args: SaleArgs) {
+    let token = token_result().expect("No token");
+    assert_eq!(token.owner_id, owner_id, "Must be token owner");
+    let approval_id = token
+      .approved_account_ids
+      .and_then(|approved_account_ids| approved_account_ids.get(&env::current_account_id()).copied())
+      .expect("The market is not approved for this token, call nft_approve first");
+
+    self.internal_list_sale(nft_contract_id, token_id, owner_id, approval_id, sale_args, false);
+  }
+
+  //updates the price for a sale on the market, optionally switching the currency it's in
   #[payable]
-  pub fn update_price(&mut self, nft_contract_id: AccountId, token_id: String, price: U128) {
+  pub fn update_price(&mut self, nft_contract_id: AccountId, token_id: String, price: U128, currency: Option<Currency>) {
     assert_one_yocto();
 
     //create the unique sale ID from the nft contract and token
@@ -73,10 +216,106 @@ impl Contract {
 
     //assert that the caller of the function is the sale owner
     assert_eq!(env::predecessor_account_id(), sale.owner_id, "Must be sale owner");
-    //set the sale conditions equal to the passed in price
-    sale.sale_conditions = price;
+    //the reserve price and currency of an auction are final once there are bids
+    assert!(!sale.has_bids(), "Cannot update an auction with bids");
+    //the price curve of a dutch auction is set when listing
+    assert!(!matches!(sale.sale_type, SaleType::DutchAuction(_)), "Cannot update a dutch auction, relist it instead");
+    //the new price has to be at least the min price of the collection in the new currency, and the collection must
+    //still be whitelisted
+    let currency = currency.unwrap_or_else(|| sale.currency.clone());
+    self.assert_approved_currency(&currency);
+    self.assert_listing_allowed(&contract_id, price, &currency);
+    //the sale moves in the order book when its price or currency changes
+    self.internal_remove_from_order_book(&sale);
+    sale.currency = currency;
+    //set the sale price equal to the passed in price
+    sale.price = price;
     //insert the sale back into the map for the unique sale ID
     self.sales.insert(&contract_and_token_id, &sale);
+    self.internal_add_to_order_book(&sale);
+
+    MarketEvent::ListingUpdate([(&sale).into()]).emit();
+  }
+
+  //checks a sale against the nft contract. Can be called by anyone. If the seller doesn't own the token anymore or the
+  //market's approval was revoked, the sale can't go through: it is taken down and the caller gets the storage it took
+  //up from the seller's storage deposit. Returns whether the sale was taken down
+  pub fn verify_sale(&mut self, contract_and_token_id: ContractAndTokenId) -> Promise {
+    let sale = self.sales.get(&contract_and_token_id).expect("No sale");
+    //the market owns the tokens it holds in custody, so their sales can't go stale
+    assert!(!sale.custodial, "The market holds this token");
+
+    ext_nft_contract::ext(sale.nft_contract_id.parse().unwrap())
+      .with_static_gas(GAS_FOR_NFT_TOKEN)
+      .nft_token(sale.token_id)
+      .then(
+        Self::ext(env::current_account_id())
+          .with_static_gas(GAS_FOR_RESOLVE_VERIFY_SALE)
+          .resolve_verify_sale(contract_and_token_id, sale.approval_id, env::predecessor_account_id()),
+      )
+  }
+
+  //private method used to resolve the nft_token call of verify_sale
+  #[private]
+  pub fn resolve_verify_sale(&mut self, contract_and_token_id: ContractAndTokenId, approval_id: u64, caller_id: AccountId) -> bool {
+    //the sale may have been sold or relisted in the meantime, then it isn't the one that was checked. A token put in
+    //custody since then is listed with approval ID 0, which could match the one that was checked, so it's left alone too
+    let sale = match self.sales.get(&contract_and_token_id) {
+      Some(sale) if sale.approval_id == approval_id && !sale.custodial => sale,
+      _ => return false,
+    };
+
+    //if the nft contract couldn't be reached, nothing is changed
+    if !matches!(env::promise_result(0), PromiseResult::Successful(_)) {
+      return false;
+    }
+    //the sale is still valid if the seller owns the token and the market is approved with the same approval ID
+    let valid = token_result().is_some_and(|token| {
+      token.owner_id == sale.owner_id
+        && token
+          .approved_account_ids
+          .is_some_and(|approved_account_ids| approved_account_ids.get(&env::current_account_id()) == Some(&approval_id))
+    });
+    if valid {
+      return false;
+    }
+
+    let sale = self.internal_drop_sale(sale.nft_contract_id.parse().unwrap(), sale.token_id);
+
+    //the storage the sale took up goes to the caller instead of back to the seller
+    let seller_deposit = self.storage_deposits.get(&sale.owner_id).unwrap_or(0);
+    let reward = seller_deposit.min(STORAGE_PER_SALE);
+    if reward > 0 {
+      self.storage_deposits.insert(&sale.owner_id, &(seller_deposit - reward));
+      Promise::new(caller_id).transfer(reward);
+    }
+    true
+  }
+
+  //reads up to `limit` sales starting at `from_index` and removes the expired ones, freeing the storage their owners paid for.
+  //Can be called by anyone. Returns how many sales were removed and the index the next call should start from
+  pub fn prune_expired(&mut self, from_index: Option<U64>, limit: u64) -> PruneResult {
+    let mut index = from_index.unwrap_or(U64(0)).0;
+    let mut pruned = 0;
+
+    for _ in 0..limit {
+      let sale = match self.sales.values_as_vector().get(index) {
+        Some(sale) => sale,
+        None => break,
+      };
+      //removing a sale moves the last one into its slot, so the same index is read again instead of moving on
+      if sale.is_expired() {
+        self.internal_drop_sale(sale.nft_contract_id.parse().unwrap(), sale.token_id);
+        pruned += 1;
+      } else {
+        index += 1;
+      }
+    }
+
+    PruneResult {
+      pruned: U64(pruned),
+      next_index: U64(index),
+    }
   }
 
   //place an offer on a specific sale. The sale will go through as long as your deposit is greater than or equal to the list price
@@ -97,99 +336,207 @@ impl Contract {
     //get the buyer ID which is the person who called the function and make sure they're not the owner of the sale
     let buyer_id = env::predecessor_account_id();
     assert_ne!(sale.owner_id, buyer_id, "Cannot bid on your own sale."); 
+    //make sure the listing is still up
+    assert!(!sale.is_expired(), "The sale has expired");
+    self.assert_whitelisted(&contract_id);
+
+    //sales listed in a fungible token have to be bought through ft_transfer_call
+    assert_eq!(sale.currency, Currency::Near, "This sale must be paid in {:?}", sale.currency);
+    //auctions are sold through place_bid and settle_auction
+    assert!(!matches!(sale.sale_type, SaleType::Auction(_)), "This sale is an auction, use place_bid");
 
-    //(dot 0 converts from U128 to u128)
-    //get the u128 price of the token (dot 0 converts from U128 to u128)
-    let price = sale.sale_conditions.0;
+    //get the u128 price the token is currently sold for
+    let price = sale.current_price();
 
     //make sure the deposit is greater than the price
     assert!(deposit >= price, "Attached deposit must be greater than or equal to the current price: {:?}", price);
-    
-    //process the purchase (which will remove the sale, transfer and get the payout from the nft contract, and then distribute royalties) 
+
+    //process the purchase (which will remove the sale, transfer and get the payout from the nft contract, and then distribute royalties).
+    //only the price is charged, the rest of the deposit is refunded once the purchase is resolved
     self.process_purchase(
       contract_id,
       token_id,
+      U128(price),
       U128(deposit),
       buyer_id,
+      None,
+      false,
+      GAS_FOR_RESOLVE_PURCHASE,
     );
   }
 
-  //private function used when a sale is purchased. 
-    //this will remove the sale, transfer and get the payout from the nft contract, and then distribute royalties
-  #[private]
-  pub fn process_purchase(&mut self, nft_contract_id: AccountId, token_id: String, price: U128, buyer_id: AccountId) {
-     //get the sale object by removing the sale
-    let sale = self.internal_remove_sale(nft_contract_id.clone(), token_id.clone());
+  //buys the cheapest fixed price NEAR listings of a collection, at most `max_count` of them for at most `max_total_price`
+  //in total. The attached deposit has to cover `max_total_price`, what isn't spent is refunded right away. Every listing is
+  //bought like a regular `buy`, so one that fails to transfer is refunded on its own without affecting the others.
+  //every purchase needs its own GAS (GAS_FOR_SWEEP_ITEM), so `max_count` is at most MAX_SWEEP_ITEMS and the GAS for all of
+  //them has to be attached. Returns how many were bought
+  #[payable]
+  pub fn sweep(&mut self, nft_contract_id: AccountId, max_count: u32, max_total_price: U128) -> U64 {
+    require!(
+      (1..=MAX_SWEEP_ITEMS).contains(&max_count),
+      format!("A sweep buys between 1 and {} listings", MAX_SWEEP_ITEMS)
+    );
+    let required_gas = GAS_FOR_SWEEP.0 + GAS_FOR_SWEEP_ITEM.0 * max_count as u64;
+    require!(
+      env::prepaid_gas().0 >= required_gas,
+      format!("Attach at least {} TGas to sweep {} listings", required_gas / 1_000_000_000_000, max_count)
+    );
+    let deposit = env::attached_deposit();
+    assert!(
+      deposit >= max_total_price.0,
+      "Attached deposit must be greater than or equal to the max total price: {:?}",
+      max_total_price
+    );
+    self.assert_whitelisted(&nft_contract_id);
+    let buyer_id = env::predecessor_account_id();
 
-    //initiate a cross contract call to the nft contract. This will transfer the token to the buyer and return
-    //a payout object used for the market to distribute funds to the appropriate accounts.
-    ext_contract::ext(nft_contract_id)
-      .with_attached_deposit(1)
-      .with_static_gas(GAS_FOR_NFT_TRANSFER)
-      .nft_transfer_payout(
-        buyer_id.clone(), 
-        token_id, 
-        sale.approval_id, 
-        "payout from market".to_string(),
-        price,
-        10
-      ).then(
-        Self::ext(env::current_account_id())
-          .with_static_gas(GAS_FOR_RESOLVE_PURCHASE)
-          .resolve_purchase(
-            buyer_id,
-            price
-          )
+    //pick the listings from the cheapest up, as long as they fit in what's left of the budget.
+    //they're collected first since the sales can't be removed while going through the order book
+    let mut budget = max_total_price.0;
+    let sales: Vec<Sale> = self
+      .internal_sorted_sales(nft_contract_id.clone(), Currency::Near, None, None)
+      .filter(|sale| sale.owner_id != buyer_id)
+      .take(max_count as usize)
+      .take_while(|sale| {
+        let fits = sale.price.0 <= budget;
+        if fits {
+          budget -= sale.price.0;
+        }
+        fits
+      })
+      .collect();
+
+    let mut spent = 0;
+    let mut bought = 0;
+    for sale in sales {
+      //the buyer is refunded the price of the listing if its transfer fails
+      self.process_purchase(
+        nft_contract_id.clone(),
+        sale.token_id,
+        sale.price,
+        sale.price,
+        buyer_id.clone(),
+        None,
+        false,
+        GAS_FOR_RESOLVE_SWEEP_PURCHASE,
       );
+      spent += sale.price.0;
+      bought += 1;
+    }
+    assert!(bought > 0, "No listing to sweep within the max total price");
+
+    if deposit > spent {
+      Promise::new(buyer_id).transfer(deposit - spent);
+    }
+    U64(bought)
   }
 
   /*
   private method used to resolve the promise when calling nft_transfer_payout. This will take the payout object and 
-  check to see if it's authentic and there's no problems. If everything is fine, it will pay the accounts. If there's a problem,
-  it will refund the buyer for the price. 
+  check to see if it's authentic and there's no problems. If everything is fine, it will pay the accounts and refund
+  whatever the buyer paid above the price. If there's a problem, it will refund the buyer everything they paid.
+  The payout covers the price minus the fee, which goes to the fee recipient.
   */
   #[private]
-  pub fn resolve_purchase(&mut self, buyer_id: AccountId, price: U128) -> U128 {
+  pub fn resolve_purchase(&mut self, purchase: Purchase) -> PurchaseResult {
     // checking for payout information returned from the nft_transfer_payout method
-    let payout_option = promise_result_as_success().and_then(|value| {
-      near_sdk::serde_json::from_slice::<Payout>(&value)
-        .ok()
-        .and_then(|payout_object| {
-          //we'll check if length of the payout object is > 10 or it's empty. In either case, we return None
-          if payout_object.payout.len() > 10 || payout_object.payout.is_empty() {
-            env::log_str("Cannot have more than 10 royalties");
-            None
-          } else {
-            let mut remainder = price.0;
-            for &value in payout_object.payout.values() {
-              remainder = remainder.checked_sub(value.0)?;
-            }
-            if remainder == 0 || remainder == 1 {
-              Some(payout_object.payout)
-            } else {
-              None
-            }
-          }
-        })
-    });
+    let payout_option = self.internal_settle_custody(&purchase, verified_payout(purchase.net_price()));
 
-    // if the payout option was some payout, we set this payout variable equal to that some payout
-    let payout = if let Some(payout_option) = payout_option {
-      payout_option
+    let refunded = if let Some(payout) = payout_option {
+      // NEAR payouts
+      self.internal_distribute_near(payout, purchase.fee.0);
+      self.internal_record_seller_volume(&purchase);
+      self.internal_record_trade(&purchase, &Currency::Near);
+      purchase.deposit.0 - purchase.price.0
+    } else {
+      purchase.deposit.0
+    };
+
+    if refunded > 0 {
+      Promise::new(purchase.buyer_id.clone()).transfer(refunded);
+    }
+
+    self.internal_emit_purchase(&purchase, Currency::Near, refunded)
+  }
+
+  /*
+  same as resolve_purchase for sales paid in fungible tokens. The payouts and the fee are sent with ft_transfer and
+  the returned value is the amount the FT contract refunds to the buyer: what was paid above the price if the purchase
+  went through, everything otherwise. Escrowed tokens aren't part of an ft_on_transfer call anymore, so they're sent back
+  to the buyer instead.
+  */
+  #[private]
+  pub fn resolve_ft_purchase(&mut self, purchase: Purchase, ft_token_id: FungibleTokenId, escrowed: bool) -> U128 {
+    // checking for payout information returned from the nft_transfer_payout method
+    let refunded = if let Some(payout) = self.internal_settle_custody(&purchase, verified_payout(purchase.net_price())) {
+      // FT payouts and the fee
+      for (receiver_id, amount) in payout {
+        self.internal_ft_transfer(&ft_token_id, receiver_id, amount.0);
+      }
+      self.internal_ft_transfer(&ft_token_id, self.fee_recipient.clone(), purchase.fee.0);
+      self.internal_record_trade(&purchase, &Currency::FungibleToken(ft_token_id.clone()));
+      purchase.deposit.0 - purchase.price.0
     } else {
-      Promise::new(buyer_id).transfer(u128::from(price));
-      return price;
+      purchase.deposit.0
     };
 
-    // NEAR payouts
-    for (receiver_id, amount) in payout {
-      let fee_amount = amount.0 * self.fee.0 / 100;
-      Promise::new(self.fee_recipient.clone()).transfer(fee_amount);
-      Promise::new(receiver_id).transfer(amount.0 - fee_amount);
+    self.internal_emit_purchase(&purchase, Currency::FungibleToken(ft_token_id.clone()), refunded);
+
+    if escrowed {
+      self.internal_ft_transfer(&ft_token_id, purchase.buyer_id, refunded);
+      U128(0)
+    } else {
+      U128(refunded)
     }
+  }
+}
+
+impl Contract {
+  //internal function used when a sale is purchased. 
+    //this will remove the sale, transfer and get the payout from the nft contract, and then distribute royalties
+    //the price is in NEAR unless the fungible token it was paid with is passed in. `deposit` is what the buyer paid, anything
+    //above the price is refunded. `escrowed` tells if the fungible tokens were already held by the market (e.g. a winning bid)
+    //rather than just sent through ft_on_transfer
+  #[allow(clippy::too_many_arguments)]
+  pub(crate) fn process_purchase(&mut self, nft_contract_id: AccountId, token_id: String, price: U128, deposit: U128, buyer_id: AccountId, ft_token_id: Option<FungibleTokenId>, escrowed: bool, resolve_gas: Gas) -> Promise {
+     //get the sale object by removing the sale
+    let sale = self.internal_remove_sale(nft_contract_id.clone(), token_id.clone());
+
+    //the fee is taken from the gross price, the rest is what the nft contract splits into the payout
+    let fee = U128(self.internal_fee_amount(&sale.owner_id, &nft_contract_id, price.0));
+    let purchase = Purchase {
+      nft_contract_id: nft_contract_id.clone(),
+      token_id: token_id.clone(),
+      seller_id: sale.owner_id,
+      buyer_id: buyer_id.clone(),
+      price,
+      fee,
+      deposit,
+      custodial: sale.custodial,
+    };
 
-    //return the price payout out
-    price
+    //the callback that distributes the funds depends on how the sale was paid
+    let resolve = Self::ext(env::current_account_id())
+      .with_static_gas(resolve_gas);
+    let resolve = match ft_token_id {
+      Some(ft_token_id) => resolve.resolve_ft_purchase(purchase, ft_token_id, escrowed),
+      None => resolve.resolve_purchase(purchase),
+    };
+
+    //initiate a cross contract call to the nft contract. This will transfer the token to the buyer and return
+    //a payout object used for the market to distribute funds to the appropriate accounts.
+    //a token in custody is transferred by the market as its owner, the approval ID is ignored then
+    ext_contract::ext(nft_contract_id)
+      .with_attached_deposit(1)
+      .with_static_gas(GAS_FOR_NFT_TRANSFER)
+      .nft_transfer_payout(
+        buyer_id, 
+        token_id, 
+        sale.approval_id, 
+        "payout from market".to_string(),
+        U128(price.0 - fee.0),
+        10
+      ).then(resolve)
   }
 }
 
@@ -203,7 +550,28 @@ impl Contract {
 trait ExtSelf {
     fn resolve_purchase(
         &mut self,
-        buyer_id: AccountId,
-        price: U128,
+        purchase: Purchase,
     ) -> Promise;
-}
\ No newline at end of file
+
+    fn resolve_ft_purchase(
+        &mut self,
+        purchase: Purchase,
+        ft_token_id: FungibleTokenId,
+        escrowed: bool,
+    ) -> Promise;
+
+    fn resolve_list(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: String,
+        owner_id: AccountId,
+        sale_args: SaleArgs,
+    );
+
+    fn resolve_verify_sale(
+        &mut self,
+        contract_and_token_id: ContractAndTokenId,
+        approval_id: u64,
+        caller_id: AccountId,
+    ) -> bool;
+}
@@ -58,6 +58,8 @@ impl Contract {
             .take(limit.unwrap_or(0) as usize) 
             //we'll map the token IDs which are strings into Sale objects
             .map(|token_id| self.sales.get(&token_id).unwrap())
+            //expired sales can't be bought anymore so they're left out
+            .filter(|sale| !sale.is_expired())
             //since we turned the keys into an iterator, we need to turn it back into a vector to return
             .collect()
     }
@@ -109,6 +111,8 @@ impl Contract {
             .take(limit.unwrap_or(0) as usize) 
             //we'll map the token IDs which are strings into Sale objects by passing in the unique sale ID (contract + DELIMITER + token ID)
             .map(|token_id| self.sales.get(&format!("{}{}{}", nft_contract_id, DELIMETER, token_id)).unwrap())
+            //expired sales can't be bought anymore so they're left out
+            .filter(|sale| !sale.is_expired())
             //since we turned the keys into an iterator, we need to turn it back into a vector to return
             .collect()
     }
@@ -116,7 +120,12 @@ impl Contract {
     //get a sale information for a given unique sale ID (contract + DELIMITER + token ID)
     pub fn get_sale(&self, nft_contract_token: ContractAndTokenId) -> Option<Sale> {
         //try and get the sale object for the given unique sale ID. Will return an option since
-        //we're not guaranteed that the unique sale ID passed in will be valid.
-        self.sales.get(&nft_contract_token)
+        //we're not guaranteed that the unique sale ID passed in will be valid. Expired sales are left out
+        self.sales.get(&nft_contract_token).filter(|sale| !sale.is_expired())
+    }
+
+    //get the price a sale can be bought for right now. For dutch auctions this follows the price curve
+    pub fn get_current_price(&self, nft_contract_token: ContractAndTokenId) -> Option<U128> {
+        self.get_sale(nft_contract_token).map(|sale| U128(sale.current_price()))
     }
 }
\ No newline at end of file
@@ -0,0 +1,95 @@
+use crate::*;
+
+//how many trades are kept per token. Older trades are dropped as new ones come in, so the storage paid by the market stays bounded
+pub const TRADE_HISTORY_SIZE: usize = 20;
+
+//sales of a collection in one currency. Amounts in different currencies can't be added up, so every currency has its own
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionStats {
+    pub currency: Currency,
+    pub last_sale_price: U128,
+    //sum of the prices of every sale
+    pub total_volume: U128,
+    pub sale_count: U64,
+    //highest price a token of the collection was sold for
+    pub all_time_high: U128,
+    //when the last sale went through (nanoseconds)
+    pub last_sale_at: U64,
+}
+
+//a completed sale of a token
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Trade {
+    pub seller_id: AccountId,
+    pub buyer_id: AccountId,
+    pub price: U128,
+    pub currency: Currency,
+    //when the sale went through (nanoseconds)
+    pub timestamp: U64,
+}
+
+#[near_bindgen]
+impl Contract {
+    //views
+
+    //sale statistics of a collection, one entry per currency it was sold in
+    pub fn get_collection_stats(&self, nft_contract_id: AccountId) -> Vec<CollectionStats> {
+        self.collection_stats.get(&nft_contract_id).unwrap_or_default()
+    }
+
+    //last trades of a token (`contract + DELIMITER + token ID`), oldest first
+    pub fn get_token_trade_history(&self, contract_and_token_id: ContractAndTokenId) -> Vec<Trade> {
+        self.trade_history.get(&contract_and_token_id).unwrap_or_default()
+    }
+}
+
+impl Contract {
+    //adds a purchase that went through to the stats of its collection and the trade history of its token. The market
+    //pays for that storage, so only trades of whitelisted collections are recorded: anyone could fill it up otherwise
+    pub(crate) fn internal_record_trade(&mut self, purchase: &Purchase, currency: &Currency) {
+        if self.whitelist.get(&purchase.nft_contract_id).is_none() {
+            return;
+        }
+
+        let now = env::block_timestamp();
+        let price = purchase.price.0;
+
+        //update the stats of the collection in the currency of the purchase, starting them if it's the first sale in it
+        let mut stats = self.collection_stats.get(&purchase.nft_contract_id).unwrap_or_default();
+        match stats.iter_mut().find(|stats| stats.currency == *currency) {
+            Some(stats) => {
+                stats.last_sale_price = purchase.price;
+                stats.total_volume = U128(stats.total_volume.0 + price);
+                stats.sale_count = U64(stats.sale_count.0 + 1);
+                stats.all_time_high = U128(stats.all_time_high.0.max(price));
+                stats.last_sale_at = U64(now);
+            }
+            None => stats.push(CollectionStats {
+                currency: currency.clone(),
+                last_sale_price: purchase.price,
+                total_volume: purchase.price,
+                sale_count: U64(1),
+                all_time_high: purchase.price,
+                last_sale_at: U64(now),
+            }),
+        }
+        self.collection_stats.insert(&purchase.nft_contract_id, &stats);
+
+        //append the trade to the token's history, dropping the oldest one once it is full
+        let contract_and_token_id = format!("{}{}{}", purchase.nft_contract_id, DELIMETER, purchase.token_id);
+        let mut trades = self.trade_history.get(&contract_and_token_id).unwrap_or_default();
+        if trades.len() >= TRADE_HISTORY_SIZE {
+            trades.remove(0);
+        }
+        trades.push(Trade {
+            seller_id: purchase.seller_id.clone(),
+            buyer_id: purchase.buyer_id.clone(),
+            price: purchase.price,
+            currency: currency.clone(),
+            timestamp: U64(now),
+        });
+        self.trade_history.insert(&contract_and_token_id, &trades);
+    }
+}
@@ -1,15 +1,267 @@
 use crate::*;
 
+//a whitelisted TokenizedCard
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WhitelistEntry {
+  //lowest price its cards can be listed for in NEAR
+  pub min_price_per_token: U128,
+  //lowest price in each fungible token. Their amounts aren't in NEAR units, so cards can only be listed in the
+  //fungible tokens that have a min price of their own
+  pub ft_min_prices: HashMap<FungibleTokenId, U128>,
+  //fee in basis points charged on its sales instead of the default fee
+  pub fee_bps: Option<u16>,
+}
+
+#[near_bindgen]
 impl Contract {
-  pub fn allowlist_card(&mut self, nft_contract_id: AccountId, min_price_per_token: U128) {
+  //whitelists a TokenizedCard for listing, or changes its min prices and fee override. Owner or whitelist manager only
+  #[payable]
+  pub fn allowlist_card(
+    &mut self,
+    nft_contract_id: AccountId,
+    min_price_per_token: U128,
+    fee_bps: Option<u16>,
+    ft_min_prices: Option<HashMap<FungibleTokenId, U128>>,
+  ) {
     //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
     assert_one_yocto();
-    self.whitelist.insert(&nft_contract_id, &min_price_per_token);
+    self.assert_role(Role::WhitelistManager);
+    if let Some(fee_bps) = fee_bps {
+      assert!(fee_bps <= MAX_FEE_BPS, "Fee can't be more than {} basis points", MAX_FEE_BPS);
+    }
+    let ft_min_prices = ft_min_prices.unwrap_or_default();
+    self.whitelist.insert(&nft_contract_id, &WhitelistEntry {
+      min_price_per_token,
+      fee_bps,
+      ft_min_prices: ft_min_prices.clone(),
+    });
+    //sales that weren't purged yet can be bought again
+    self.delisted_nft_contract_ids.remove(&nft_contract_id);
+
+    MarketEvent::WhitelistAdd([WhitelistUpdate {
+      nft_contract_id,
+      min_price_per_token: Some(min_price_per_token),
+      fee_bps,
+      ft_min_prices: Some(ft_min_prices),
+      updated_by: env::predecessor_account_id(),
+    }]).emit();
   }
 
+  //removes a TokenizedCard from the whitelist. Owner or whitelist manager only
+  #[payable]
   pub fn disallow_card(&mut self, nft_contract_id: AccountId) {
     //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
     assert_one_yocto();
+    self.assert_role(Role::WhitelistManager);
     self.whitelist.remove(&nft_contract_id);
+    //the listed cards, bundles and offers can't be traded anymore. They are removed with purge_collection
+    //since there may be too many of them for one transaction
+    if self.internal_has_market_entries(&nft_contract_id) {
+      self.delisted_nft_contract_ids.insert(&nft_contract_id);
+    }
+
+    MarketEvent::WhitelistRemove([WhitelistUpdate {
+      nft_contract_id,
+      min_price_per_token: None,
+      fee_bps: None,
+      ft_min_prices: None,
+      updated_by: env::predecessor_account_id(),
+    }]).emit();
+  }
+
+  //adds a fungible token that sales can be listed and paid in. Owner or whitelist manager only
+  #[payable]
+  pub fn allowlist_ft(&mut self, ft_token_id: FungibleTokenId) {
+    //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
+    assert_one_yocto();
+    self.assert_role(Role::WhitelistManager);
+    self.approved_ft_token_ids.insert(&ft_token_id);
+
+    MarketEvent::CurrencyAdd([CurrencyUpdate {
+      ft_token_id,
+      updated_by: env::predecessor_account_id(),
+    }]).emit();
+  }
+
+  //removes a fungible token. Sales listed in it can no longer be bought until they're repriced in another currency.
+  //Owner or whitelist manager only
+  #[payable]
+  pub fn disallow_ft(&mut self, ft_token_id: FungibleTokenId) {
+    //assert that the user has attached exactly 1 yoctoNEAR (for security reasons)
+    assert_one_yocto();
+    self.assert_role(Role::WhitelistManager);
+    self.approved_ft_token_ids.remove(&ft_token_id);
+
+    MarketEvent::CurrencyRemove([CurrencyUpdate {
+      ft_token_id,
+      updated_by: env::predecessor_account_id(),
+    }]).emit();
+  }
+
+  //returns the whitelisted TokenizedCards with their min price per token and fee override
+  pub fn get_whitelist(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<(AccountId, WhitelistEntry)> {
+    //where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+    let start = u128::from(from_index.unwrap_or(U128(0)));
+
+    self.whitelist
+      .iter()
+      .skip(start as usize)
+      .take(limit.unwrap_or(0) as usize)
+      .collect()
+  }
+
+  //removes up to `limit` sales, bundles, collection offers and offers of a collection that was removed from the whitelist,
+  //in that order. Can be called by anyone, as many times as needed until everything is gone. Bids on auctions and the
+  //escrow of offers are refunded. Returns how many entries were removed
+  pub fn purge_collection(&mut self, nft_contract_id: AccountId, limit: u64) -> U64 {
+    assert!(self.delisted_nft_contract_ids.contains(&nft_contract_id), "{} is not delisted", nft_contract_id);
+
+    //collect the IDs first since the sets can't be changed while iterating over them
+    let token_ids: Vec<TokenId> = self.by_nft_contract_id
+      .get(&nft_contract_id)
+      .map(|token_ids| token_ids.iter().take(limit as usize).collect())
+      .unwrap_or_default();
+    let mut purged = token_ids.len() as u64;
+    for token_id in token_ids {
+      self.internal_drop_sale(nft_contract_id.clone(), token_id);
+    }
+
+    //the whole bundle comes down, the tokens of the other collections in it included
+    let bundle_ids: Vec<BundleId> = self.bundles_by_nft_contract_id
+      .get(&nft_contract_id)
+      .map(|bundle_ids| bundle_ids.iter().take((limit - purged) as usize).collect())
+      .unwrap_or_default();
+    purged += bundle_ids.len() as u64;
+    for bundle_id in bundle_ids {
+      self.internal_remove_bundle(bundle_id);
+    }
+
+    let offer_ids: Vec<CollectionOfferId> = self.collection_offers_by_nft_contract_id
+      .get(&nft_contract_id)
+      .map(|offer_ids| offer_ids.iter().take((limit - purged) as usize).collect())
+      .unwrap_or_default();
+    purged += offer_ids.len() as u64;
+    for offer_id in offer_ids {
+      let offer = self.internal_remove_collection_offer(offer_id).unwrap();
+      Promise::new(offer.bidder_id).transfer(offer.amount.0 * offer.quantity as u128);
+    }
+
+    //offers on single tokens, one token at a time until the limit is reached. Every offered token has at least one
+    //offer, so no more tokens than what's left of the limit are needed
+    let token_ids: Vec<TokenId> = self.offered_token_ids_by_nft_contract_id
+      .get(&nft_contract_id)
+      .map(|token_ids| token_ids.iter().take((limit - purged) as usize).collect())
+      .unwrap_or_default();
+    for token_id in token_ids {
+      if purged == limit {
+        break;
+      }
+      let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+      let buyer_ids: Vec<AccountId> = self.offers_by_token
+        .get(&contract_and_token_id)
+        .map(|offers| offers.keys().take((limit - purged) as usize).collect())
+        .unwrap_or_default();
+      purged += buyer_ids.len() as u64;
+      for buyer_id in buyer_ids {
+        let offer = self.internal_remove_offer(&contract_and_token_id, &buyer_id).unwrap();
+        Promise::new(buyer_id).transfer(offer.amount.0);
+      }
+    }
+
+    //the collection is done once everything is gone
+    if !self.internal_has_market_entries(&nft_contract_id) {
+      self.delisted_nft_contract_ids.remove(&nft_contract_id);
+    }
+    U64(purged)
+  }
+
+  //whether a collection was removed from the whitelist and still has sales, bundles, collection offers or offers to purge
+  pub fn is_collection_delisted(&self, nft_contract_id: AccountId) -> bool {
+    self.delisted_nft_contract_ids.contains(&nft_contract_id)
+  }
+
+  //returns the fungible tokens accepted besides NEAR
+  pub fn get_approved_ft_token_ids(&self) -> Vec<FungibleTokenId> {
+    self.approved_ft_token_ids.to_vec()
+  }
+
+  //checks a listing before the owner approves the market for it. Returns every reason it would be rejected, nothing if it
+  //would go through. For a dutch auction, check the floor price as well
+  pub fn validate_listing(
+    &self,
+    nft_contract_id: AccountId,
+    owner_id: AccountId,
+    price: U128,
+    currency: Option<Currency>,
+  ) -> Vec<String> {
+    let mut errors = vec![];
+    let currency = currency.unwrap_or(Currency::Near);
+    if let Err(error) = self.internal_check_listing(&nft_contract_id, price, &currency) {
+      errors.push(error);
+    }
+    if let Currency::FungibleToken(ft_token_id) = currency {
+      if !self.approved_ft_token_ids.contains(&ft_token_id) {
+        errors.push(format!("{} is not an accepted currency", ft_token_id));
+      }
+    }
+    //the owner needs storage for 1 EXTRA sale
+    let owner_paid_storage = self.storage_deposits.get(&owner_id).unwrap_or(0);
+    let owner_storage_required = (self.internal_storage_entries(&owner_id) + 1) as u128 * STORAGE_PER_SALE;
+    if owner_paid_storage < owner_storage_required {
+      errors.push(format!(
+        "Insufficient storage paid: {}, for {} sales at {} rate of per sale",
+        owner_paid_storage, owner_storage_required / STORAGE_PER_SALE, STORAGE_PER_SALE
+      ));
+    }
+    errors
+  }
+}
+
+impl Contract {
+  //whether the collection has any sale, bundle, collection offer or offer on the market
+  pub(crate) fn internal_has_market_entries(&self, nft_contract_id: &AccountId) -> bool {
+    self.by_nft_contract_id.get(nft_contract_id).is_some()
+      || self.bundles_by_nft_contract_id.get(nft_contract_id).is_some()
+      || self.collection_offers_by_nft_contract_id.get(nft_contract_id).is_some()
+      || self.offered_token_ids_by_nft_contract_id.get(nft_contract_id).is_some()
+  }
+
+  //min price of a whitelisted collection in the given currency
+  pub(crate) fn internal_min_price(&self, nft_contract_id: &AccountId, currency: &Currency) -> Result<Balance, String> {
+    let entry = match self.whitelist.get(nft_contract_id) {
+      Some(entry) => entry,
+      None if self.delisted_nft_contract_ids.contains(nft_contract_id) => {
+        return Err(format!("{} has been removed from the whitelist", nft_contract_id));
+      }
+      None => return Err(format!("{} is not whitelisted", nft_contract_id)),
+    };
+    match currency {
+      Currency::Near => Ok(entry.min_price_per_token.0),
+      Currency::FungibleToken(ft_token_id) => entry
+        .ft_min_prices
+        .get(ft_token_id)
+        .map(|min_price| min_price.0)
+        .ok_or_else(|| format!("{} has no min price in {} and can't be listed in it", nft_contract_id, ft_token_id)),
+    }
+  }
+
+  //a sale can only be listed, or have its price changed, if the collection is whitelisted and the price is at least its
+  //min price in the currency of the sale
+  pub(crate) fn internal_check_listing(&self, nft_contract_id: &AccountId, price: U128, currency: &Currency) -> Result<(), String> {
+    let min_price = self.internal_min_price(nft_contract_id, currency)?;
+    if price.0 < min_price {
+      return Err(format!(
+        "Price must be greater than or equal to the min price of {}: {}",
+        nft_contract_id, min_price
+      ));
+    }
+    Ok(())
+  }
+
+  pub(crate) fn assert_listing_allowed(&self, nft_contract_id: &AccountId, price: U128, currency: &Currency) {
+    if let Err(error) = self.internal_check_listing(nft_contract_id, price, currency) {
+      env::panic_str(&error);
+    }
   }
-}
\ No newline at end of file
+}
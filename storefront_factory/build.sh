@@ -0,0 +1 @@
+set -e && (cd ../tokenized_card && bash ./build.sh) && RUSTFLAGS='-C link-arg=-s' cargo build --target wasm32-unknown-unknown --release && mkdir -p ../out && cp target/wasm32-unknown-unknown/release/*.wasm ../out/storefrontFactory.wasm
@@ -0,0 +1,312 @@
+use near_contract_standards::non_fungible_token::metadata::NFTContractMetadata;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::UnorderedMap;
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json;
+use near_sdk::{
+    env, is_promise_success, near_bindgen, AccountId, Balance, BorshStorageKey, Gas,
+    PanicOnDefault, Promise,
+};
+
+/// Compiled TokenizedCard contract deployed to every new storefront. `build.sh` rebuilds it from `tokenized_card` first
+const TOKENIZED_CARD_CODE: &[u8] = include_bytes!("../../out/tokenizedCard.wasm");
+
+/// Bytes reserved on top of the code for the account record and the initial TokenizedCard state
+const STOREFRONT_EXTRA_BYTES: u128 = 10_000;
+
+const GAS_FOR_STOREFRONT_NEW: Gas = Gas(50_000_000_000_000);
+const GAS_FOR_ON_STOREFRONT_CREATED: Gas = Gas(15_000_000_000_000);
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct StorefrontFactory {
+    /// Every storefront deployed by this factory mapped to the account that owns it
+    storefronts: UnorderedMap<AccountId, AccountId>,
+}
+
+#[derive(BorshSerialize, BorshStorageKey)]
+enum StorageKey {
+    Storefronts,
+}
+
+/// Init args of a new storefront. Amounts are `U128` strings: JSON numbers can't hold a price in yoctoNEAR
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct StorefrontArgs {
+    owner_id: AccountId,
+    metadata: NFTContractMetadata,
+    total_supply: U128,
+    cost_per_token: U128,
+    open_edition: Option<bool>,
+}
+
+/// Registry entry returned by the views
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Storefront {
+    pub account_id: AccountId,
+    pub owner_id: AccountId,
+}
+
+#[near_bindgen]
+impl StorefrontFactory {
+    #[init]
+    pub fn new() -> Self {
+        assert!(!env::state_exists(), "Already initialized");
+        Self {
+            storefronts: UnorderedMap::new(StorageKey::Storefronts),
+        }
+    }
+
+    /// Creates `name.<factory>`, deploys TokenizedCard to it and initializes it with the caller as owner.
+    /// The deposit must cover the storefront's storage, whatever isn't needed is refunded once it's created.
+    #[payable]
+    pub fn create_storefront(
+        &mut self,
+        name: String,
+        cost_per_token: U128,
+        total_supply: U128,
+        metadata: NFTContractMetadata,
+        open_edition: Option<bool>,
+    ) -> Promise {
+        metadata.assert_valid();
+
+        let storefront_id: AccountId = format!("{}.{}", name, env::current_account_id())
+            .parse()
+            .unwrap_or_else(|_| env::panic_str("Invalid storefront name"));
+        assert!(
+            self.storefronts.get(&storefront_id).is_none(),
+            "Storefront {} already exists",
+            storefront_id
+        );
+
+        let owner_id = env::predecessor_account_id();
+        let attached_deposit = env::attached_deposit();
+        let required_deposit = self.storefront_minimum_deposit().0;
+        assert!(
+            attached_deposit >= required_deposit,
+            "Requires minimum deposit of {}",
+            required_deposit
+        );
+
+        let args = StorefrontArgs {
+            owner_id: owner_id.clone(),
+            metadata,
+            total_supply,
+            cost_per_token,
+            open_edition,
+        };
+
+        Promise::new(storefront_id.clone())
+            .create_account()
+            .transfer(required_deposit)
+            .deploy_contract(TOKENIZED_CARD_CODE.to_vec())
+            .function_call(
+                "new".to_string(),
+                serde_json::to_vec(&args).unwrap(),
+                0,
+                GAS_FOR_STOREFRONT_NEW,
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_STOREFRONT_CREATED)
+                    .on_storefront_created(
+                        storefront_id,
+                        owner_id,
+                        U128(attached_deposit),
+                        U128(required_deposit),
+                    ),
+            )
+    }
+
+    /// Registers the storefront and refunds the unused deposit, or the whole deposit if the deployment failed
+    #[private]
+    pub fn on_storefront_created(
+        &mut self,
+        storefront_id: AccountId,
+        owner_id: AccountId,
+        attached_deposit: U128,
+        required_deposit: U128,
+    ) -> bool {
+        if !is_promise_success() {
+            env::log_str(&format!("Failed to create storefront {}", storefront_id));
+            Promise::new(owner_id).transfer(attached_deposit.0);
+            return false;
+        }
+
+        let initial_storage_usage = env::storage_usage();
+        self.storefronts.insert(&storefront_id, &owner_id);
+        let registry_cost =
+            env::storage_byte_cost() * Balance::from(env::storage_usage() - initial_storage_usage);
+
+        let refund = attached_deposit
+            .0
+            .saturating_sub(required_deposit.0 + registry_cost);
+        if refund > 0 {
+            Promise::new(owner_id).transfer(refund);
+        }
+        true
+    }
+
+    /// views
+    pub fn storefront_minimum_deposit(&self) -> U128 {
+        U128(
+            (TOKENIZED_CARD_CODE.len() as u128 + STOREFRONT_EXTRA_BYTES) * env::storage_byte_cost(),
+        )
+    }
+
+    pub fn get_storefront_count(&self) -> u64 {
+        self.storefronts.len()
+    }
+
+    pub fn get_storefront(&self, account_id: AccountId) -> Option<Storefront> {
+        self.storefronts
+            .get(&account_id)
+            .map(|owner_id| Storefront {
+                account_id,
+                owner_id,
+            })
+    }
+
+    pub fn get_storefronts(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<Storefront> {
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        self.storefronts
+            .iter()
+            .skip(start as usize)
+            .take(limit.unwrap_or(50) as usize)
+            .map(|(account_id, owner_id)| Storefront {
+                account_id,
+                owner_id,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::{get_created_receipts, VMContextBuilder};
+    use near_sdk::{testing_env, PromiseResult, RuntimeFeesConfig, VMConfig};
+
+    const FACTORY: &str = "factory.near";
+    const OWNER: &str = "artist.near";
+    const ONE_NEAR: u128 = 1_000_000_000_000_000_000_000_000;
+
+    fn context(predecessor: &str) -> VMContextBuilder {
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id(FACTORY.parse().unwrap())
+            .predecessor_account_id(predecessor.parse().unwrap());
+        context
+    }
+
+    fn metadata() -> NFTContractMetadata {
+        NFTContractMetadata {
+            spec: "nft-1.0.0".to_string(),
+            name: "Card".to_string(),
+            symbol: "CARD".to_string(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        }
+    }
+
+    /// Runs the next calls as the callback of the storefront deployment, which ended with `result`
+    fn callback_context(result: PromiseResult) {
+        testing_env!(
+            context(FACTORY).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result]
+        );
+    }
+
+    #[test]
+    fn create_storefront_deploys_with_string_amounts() {
+        testing_env!(context(OWNER).build());
+        let mut factory = StorefrontFactory::new();
+        let required_deposit = factory.storefront_minimum_deposit().0;
+        testing_env!(context(OWNER).attached_deposit(required_deposit + ONE_NEAR).build());
+
+        factory.create_storefront("cards".to_string(), U128(ONE_NEAR), U128(100), metadata(), None);
+
+        let receipts = get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, "cards.factory.near".parse().unwrap());
+        let actions = &receipts[0].actions;
+        assert!(matches!(actions[0], VmAction::CreateAccount));
+        assert!(matches!(actions[1], VmAction::Transfer { deposit } if deposit == required_deposit));
+        assert!(matches!(&actions[2], VmAction::DeployContract { code } if code == TOKENIZED_CARD_CODE));
+        match &actions[3] {
+            VmAction::FunctionCall { function_name, args, .. } => {
+                assert_eq!(function_name, "new");
+                let args: serde_json::Value = serde_json::from_slice(args).unwrap();
+                assert_eq!(args["owner_id"], OWNER);
+                assert_eq!(args["cost_per_token"], ONE_NEAR.to_string());
+                assert_eq!(args["total_supply"], "100");
+                assert_eq!(args["metadata"]["symbol"], "CARD");
+            }
+            _ => panic!("Expected the init call"),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires minimum deposit of")]
+    fn create_storefront_requires_the_storage_deposit() {
+        testing_env!(context(OWNER).build());
+        let mut factory = StorefrontFactory::new();
+        let required_deposit = factory.storefront_minimum_deposit().0;
+        testing_env!(context(OWNER).attached_deposit(required_deposit - 1).build());
+
+        factory.create_storefront("cards".to_string(), U128(ONE_NEAR), U128(100), metadata(), None);
+    }
+
+    #[test]
+    fn failed_deployment_refunds_the_whole_deposit() {
+        testing_env!(context(OWNER).build());
+        let mut factory = StorefrontFactory::new();
+        callback_context(PromiseResult::Failed);
+
+        let created = factory.on_storefront_created(
+            "cards.factory.near".parse().unwrap(),
+            OWNER.parse().unwrap(),
+            U128(6 * ONE_NEAR),
+            U128(5 * ONE_NEAR),
+        );
+
+        assert!(!created);
+        assert_eq!(factory.get_storefront_count(), 0);
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, OWNER.parse().unwrap());
+        assert!(matches!(receipts[0].actions[0], VmAction::Transfer { deposit } if deposit == 6 * ONE_NEAR));
+    }
+
+    #[test]
+    fn created_storefront_is_registered_and_the_excess_refunded() {
+        testing_env!(context(OWNER).build());
+        let mut factory = StorefrontFactory::new();
+        callback_context(PromiseResult::Successful(vec![]));
+
+        assert!(factory.on_storefront_created(
+            "cards.factory.near".parse().unwrap(),
+            OWNER.parse().unwrap(),
+            U128(6 * ONE_NEAR),
+            U128(5 * ONE_NEAR),
+        ));
+
+        let storefront = factory.get_storefront("cards.factory.near".parse().unwrap()).unwrap();
+        assert_eq!(storefront.owner_id, OWNER.parse().unwrap());
+        //the refund is what's left after the storefront and its registry entry are paid for
+        let receipts = get_created_receipts();
+        match receipts[0].actions[0] {
+            VmAction::Transfer { deposit } => assert!(deposit < ONE_NEAR && deposit > ONE_NEAR / 2),
+            _ => panic!("Expected a refund"),
+        }
+    }
+}
+
@@ -0,0 +1,240 @@
+use crate::*;
+use near_contract_standards::non_fungible_token::core::{
+    NonFungibleTokenCore, NonFungibleTokenResolver,
+};
+use near_contract_standards::non_fungible_token::events::NftTransfer;
+use near_contract_standards::non_fungible_token::refund_deposit;
+use near_sdk::{assert_one_yocto, ext_contract, require, Gas};
+use std::collections::HashMap;
+
+const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas(5_000_000_000_000);
+const GAS_FOR_NFT_TRANSFER_CALL: Gas = Gas(25_000_000_000_000 + GAS_FOR_RESOLVE_TRANSFER.0);
+const GAS_FOR_NFT_APPROVE_ALL: Gas = Gas(10_000_000_000_000);
+
+#[ext_contract(ext_nft_receiver)]
+trait NonFungibleTokenReceiver {
+    fn nft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_id: AccountId,
+        token_id: TokenId,
+        msg: String,
+    ) -> PromiseOrValue<bool>;
+}
+
+#[ext_contract(ext_nft_approval_for_all_receiver)]
+trait NonFungibleTokenApprovalForAllReceiver {
+    fn nft_on_approve_all(&mut self, owner_id: AccountId, msg: String);
+}
+
+#[ext_contract(ext_self)]
+trait NonFungibleTokenSelfResolver {
+    fn nft_resolve_transfer(
+        &mut self,
+        previous_owner_id: AccountId,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approved_account_ids: Option<HashMap<AccountId, u64>>,
+    ) -> bool;
+}
+
+#[near_bindgen]
+impl TokenizedCard {
+    /// Approves `operator_id` to transfer every token the caller owns, now or in the future.
+    /// If `msg` is given, `nft_on_approve_all` is called on the operator.
+    #[payable]
+    pub fn nft_approve_all(
+        &mut self,
+        operator_id: AccountId,
+        msg: Option<String>,
+    ) -> Option<Promise> {
+        require!(
+            env::attached_deposit() >= 1,
+            "Requires attached deposit of at least 1 yoctoNEAR"
+        );
+        let owner_id = env::predecessor_account_id();
+
+        let initial_storage_usage = env::storage_usage();
+        let mut operators = self.operator_approvals.get(&owner_id).unwrap_or_default();
+        operators.insert(operator_id.clone());
+        self.operator_approvals.insert(&owner_id, &operators);
+        refund_deposit(env::storage_usage() - initial_storage_usage);
+
+        msg.map(|msg| {
+            require!(
+                env::prepaid_gas() > GAS_FOR_NFT_APPROVE_ALL,
+                "More gas is required"
+            );
+            ext_nft_approval_for_all_receiver::ext(operator_id)
+                .with_static_gas(env::prepaid_gas() - GAS_FOR_NFT_APPROVE_ALL)
+                .nft_on_approve_all(owner_id, msg)
+        })
+    }
+
+    /// Revokes an operator approved with `nft_approve_all` and refunds the storage it used.
+    /// (`nft_revoke_all` is the NEP-178 per-token revoke)
+    #[payable]
+    pub fn nft_revoke_approve_all(&mut self, operator_id: AccountId) {
+        assert_one_yocto();
+        let owner_id = env::predecessor_account_id();
+
+        let mut operators = match self.operator_approvals.get(&owner_id) {
+            Some(operators) => operators,
+            None => return,
+        };
+        if !operators.remove(&operator_id) {
+            return;
+        }
+
+        let initial_storage_usage = env::storage_usage();
+        if operators.is_empty() {
+            self.operator_approvals.remove(&owner_id);
+        } else {
+            self.operator_approvals.insert(&owner_id, &operators);
+        }
+        let storage_released = initial_storage_usage - env::storage_usage();
+        if storage_released > 0 {
+            Promise::new(owner_id)
+                .transfer(env::storage_byte_cost() * Balance::from(storage_released));
+        }
+    }
+
+    pub fn nft_is_approved_for_all(&self, owner_id: AccountId, operator_id: AccountId) -> bool {
+        self.operator_approvals
+            .get(&owner_id)
+            .is_some_and(|operators| operators.contains(&operator_id))
+    }
+
+    /// Transfers a token like the NFT core does, except `sender_id` may also move it through
+    /// an approval for all. A token approval takes precedence, so its `approval_id` is still
+    /// checked. The transfer event names `sender_id` as `authorized_id` unless it's the owner.
+    pub(crate) fn internal_transfer_by(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        token_id: &TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+    ) -> (AccountId, Option<HashMap<AccountId, u64>>) {
+        let owner_id = self
+            .tokens
+            .owner_by_id
+            .get(token_id)
+            .unwrap_or_else(|| env::panic_str("Token not found"));
+
+        // approvals are cleared by a transfer, a failed one rolls this back
+        let approved_account_ids = self
+            .tokens
+            .approvals_by_id
+            .as_mut()
+            .and_then(|by_id| by_id.remove(token_id));
+
+        if sender_id != &owner_id {
+            match approved_account_ids.as_ref().and_then(|approvals| approvals.get(sender_id)) {
+                Some(actual_approval_id) => require!(
+                    approval_id.is_none() || approval_id == Some(*actual_approval_id),
+                    format!(
+                        "The actual approval_id {} is different from the given approval_id {:?}",
+                        actual_approval_id, approval_id
+                    )
+                ),
+                None => require!(
+                    self.nft_is_approved_for_all(owner_id.clone(), sender_id.clone()),
+                    "Sender not approved"
+                ),
+            }
+        }
+        require!(&owner_id != receiver_id, "Current and next owner must differ");
+
+        self.tokens
+            .internal_transfer_unguarded(token_id, &owner_id, receiver_id);
+        NftTransfer {
+            old_owner_id: &owner_id,
+            new_owner_id: receiver_id,
+            token_ids: &[token_id],
+            authorized_id: Some(sender_id).filter(|sender_id| *sender_id != &owner_id),
+            memo: memo.as_deref(),
+        }
+        .emit();
+
+        (owner_id, approved_account_ids)
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenCore for TokenizedCard {
+    #[payable]
+    fn nft_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        self.internal_transfer_by(
+            &env::predecessor_account_id(),
+            &receiver_id,
+            &token_id,
+            approval_id,
+            memo,
+        );
+    }
+
+    #[payable]
+    fn nft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        assert_one_yocto();
+        require!(
+            env::prepaid_gas() > GAS_FOR_NFT_TRANSFER_CALL,
+            "More gas is required"
+        );
+        let sender_id = env::predecessor_account_id();
+        let (old_owner, old_approvals) = self.internal_transfer_by(
+            &sender_id,
+            &receiver_id,
+            &token_id,
+            approval_id,
+            memo,
+        );
+
+        ext_nft_receiver::ext(receiver_id.clone())
+            .with_static_gas(env::prepaid_gas() - GAS_FOR_NFT_TRANSFER_CALL)
+            .nft_on_transfer(sender_id, old_owner.clone(), token_id.clone(), msg)
+            .then(
+                ext_self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                    .nft_resolve_transfer(old_owner, receiver_id, token_id, old_approvals),
+            )
+            .into()
+    }
+
+    fn nft_token(&self, token_id: TokenId) -> Option<Token> {
+        self.tokens.nft_token(token_id)
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenResolver for TokenizedCard {
+    #[private]
+    fn nft_resolve_transfer(
+        &mut self,
+        previous_owner_id: AccountId,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approved_account_ids: Option<HashMap<AccountId, u64>>,
+    ) -> bool {
+        self.tokens.nft_resolve_transfer(
+            previous_owner_id,
+            receiver_id,
+            token_id,
+            approved_account_ids,
+        )
+    }
+}
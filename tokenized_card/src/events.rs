@@ -0,0 +1,53 @@
+use crate::*;
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json;
+
+/// NEP-297 events for TokenizedCard changes that aren't covered by the NEP-171 events
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+pub enum CardEvent {
+    CostPerTokenUpdate([ValueUpdate; 1]),
+    TotalSupplyUpdate([ValueUpdate; 1]),
+    NftMetadataUpdate([TokenIds; 1]),
+    /// An empty `token_ids` means the metadata of the whole contract got frozen
+    MetadataFreeze([TokenIds; 1]),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ValueUpdate {
+    pub old_value: U128,
+    pub new_value: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenIds {
+    pub token_ids: Vec<TokenId>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a CardEvent,
+}
+
+impl CardEvent {
+    pub fn emit(&self) {
+        let log = EventLog {
+            standard: "tenamint_card",
+            version: "1.0.0",
+            event: self,
+        };
+        env::log_str(&format!(
+            "EVENT_JSON:{}",
+            serde_json::to_string(&log).unwrap_or_else(|_| env::abort())
+        ));
+    }
+}
@@ -4,11 +4,20 @@ use near_contract_standards::non_fungible_token::metadata::{
 use near_contract_standards::non_fungible_token::NonFungibleToken;
 use near_contract_standards::non_fungible_token::{Token, TokenId};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LazyOption, UnorderedSet};
+use near_sdk::collections::{LazyOption, LookupMap, LookupSet, UnorderedSet};
+use near_sdk::json_types::U128;
 use near_sdk::{
-    env, near_bindgen, AccountId, Balance, BorshStorageKey, CryptoHash, PanicOnDefault, Promise,
-    PromiseOrValue,
+    assert_one_yocto, env, near_bindgen, AccountId, Balance, BorshStorageKey, CryptoHash,
+    PanicOnDefault, Promise, PromiseOrValue,
 };
+use std::collections::HashSet;
+
+use crate::events::*;
+
+mod approval_for_all;
+mod events;
+mod metadata;
+mod storage;
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
@@ -17,6 +26,18 @@ pub struct TokenizedCard {
     metadata: LazyOption<NFTContractMetadata>,
     total_supply: u128,
     cost_per_token: u128,
+    /// Whether `total_supply` may be raised after initialization
+    open_edition: bool,
+    /// NEP-145 balances buyers prepaid to cover the storage of the tokens they buy
+    storage_deposits: LookupMap<AccountId, Balance>,
+    /// Operators each owner approved to transfer all of their tokens
+    operator_approvals: LookupMap<AccountId, HashSet<AccountId>>,
+    /// Accounts besides the owner allowed to update token metadata
+    metadata_editors: UnorderedSet<AccountId>,
+    /// Tokens whose metadata can no longer be updated
+    frozen_token_metadata: LookupSet<TokenId>,
+    /// Whether the metadata of every token is frozen
+    metadata_frozen: bool,
 }
 
 #[derive(BorshSerialize, BorshStorageKey)]
@@ -27,17 +48,24 @@ enum StorageKey {
     Enumeration,
     Approval,
     TokenPerOwnerInner { account_id_hash: CryptoHash },
+    OperatorApprovals,
+    StorageDeposits,
+    MetadataEditors,
+    FrozenTokenMetadata,
 }
 
 #[near_bindgen]
 impl TokenizedCard {
-    /// Initializes the contract owned by `owner_id` with metadata, cost_per_token and toal_supply
+    /// Initializes the contract owned by `owner_id` with metadata, cost_per_token and toal_supply.
+    /// Both are passed as strings since JSON numbers can't hold a price in yoctoNEAR.
+    /// Only an `open_edition` contract can raise its total supply later on.
     #[init]
     pub fn new(
         owner_id: AccountId,
         metadata: NFTContractMetadata,
-        total_supply: u128,
-        cost_per_token: u128,
+        total_supply: U128,
+        cost_per_token: U128,
+        open_edition: Option<bool>,
     ) -> Self {
         assert!(!env::state_exists(), "Already initialized");
         metadata.assert_valid();
@@ -50,48 +78,126 @@ impl TokenizedCard {
                 Some(StorageKey::Approval),
             ),
             metadata: LazyOption::new(StorageKey::Metadata, Some(&metadata)),
-            total_supply,
-            cost_per_token,
+            total_supply: total_supply.0,
+            cost_per_token: cost_per_token.0,
+            open_edition: open_edition.unwrap_or(false),
+            storage_deposits: LookupMap::new(StorageKey::StorageDeposits),
+            operator_approvals: LookupMap::new(StorageKey::OperatorApprovals),
+            metadata_editors: UnorderedSet::new(StorageKey::MetadataEditors),
+            frozen_token_metadata: LookupSet::new(StorageKey::FrozenTokenMetadata),
+            metadata_frozen: false,
         }
     }
 
+    /// Mints as many tokens to `receiver_id` as the attached deposit covers. Each token costs
+    /// `cost_per_token` plus the storage it takes, which is paid from the caller's `storage_deposit`
+    /// balance first. The charged amount goes to the owner and the rest is refunded once.
     #[payable]
     pub fn buy(&mut self, receiver_id: AccountId) -> u64 {
+        let buyer_id = env::predecessor_account_id();
         let tokens_minted: u64 = self.tokens.owner_by_id.len();
         let mut attached_deposit = env::attached_deposit();
-        let mut count = 1;
-        loop {
-            if (tokens_minted + count) as u128 > self.total_supply {
-                // refund rest amount
-                Promise::new(env::predecessor_account_id()).transfer(attached_deposit);
-                break;
-            }
+        let prepaid_storage = self.storage_deposits.get(&buyer_id);
+        let mut storage_balance = prepaid_storage.unwrap_or(0);
+        let mut count = 0;
 
+        while ((tokens_minted + count) as u128) < self.total_supply {
             let initial_storage_usage = env::storage_usage();
 
-            let token_id = format!("TokenizedCard-{}", tokens_minted + count);
+            let token_id = format!("TokenizedCard-{}", tokens_minted + count + 1);
 
             self.internal_add_token_to_owner(&receiver_id, token_id.clone());
 
-            let required_storage_in_bytes = env::storage_usage() - initial_storage_usage;
-            let required_cost = env::storage_byte_cost() * Balance::from(required_storage_in_bytes)
-                + self.cost_per_token;
+            let storage_cost = env::storage_byte_cost()
+                * Balance::from(env::storage_usage() - initial_storage_usage);
+            let paid_from_balance = storage_cost.min(storage_balance);
+            let required_cost = storage_cost - paid_from_balance + self.cost_per_token;
 
             if required_cost > attached_deposit {
-                self.internal_remove_token_from_owner(&receiver_id, token_id.clone());
-                if attached_deposit > 1 {
-                    // refund rest amount
-                    Promise::new(env::predecessor_account_id()).transfer(attached_deposit);
-                    break;
-                }
+                self.internal_remove_token_from_owner(&receiver_id, token_id);
+                break;
             }
 
             attached_deposit -= required_cost;
-
+            storage_balance -= paid_from_balance;
             count += 1;
         }
 
-        count - 1
+        if prepaid_storage.is_some_and(|balance| balance != storage_balance) {
+            self.storage_deposits.insert(&buyer_id, &storage_balance);
+        }
+
+        let charged = self.cost_per_token * Balance::from(count);
+        if charged > 0 {
+            Promise::new(self.tokens.owner_id.clone()).transfer(charged);
+        }
+        if attached_deposit > 0 {
+            // refund rest amount
+            Promise::new(buyer_id).transfer(attached_deposit);
+        }
+
+        count
+    }
+
+    /// Reprices the tokens that are still to be minted
+    #[payable]
+    pub fn set_cost_per_token(&mut self, cost_per_token: U128) {
+        assert_one_yocto();
+        self.assert_owner();
+
+        CardEvent::CostPerTokenUpdate([ValueUpdate {
+            old_value: self.cost_per_token.into(),
+            new_value: cost_per_token,
+        }])
+        .emit();
+        self.cost_per_token = cost_per_token.0;
+    }
+
+    /// Lowers the total supply down to the number of tokens already minted,
+    /// or raises it if the contract was created as an open edition
+    #[payable]
+    pub fn set_total_supply(&mut self, total_supply: U128) {
+        assert_one_yocto();
+        self.assert_owner();
+        let total_supply = total_supply.0;
+
+        let tokens_minted = self.tokens.owner_by_id.len() as u128;
+        assert!(
+            total_supply >= tokens_minted,
+            "Total supply can't be lower than the {} tokens already minted",
+            tokens_minted
+        );
+        assert!(
+            total_supply <= self.total_supply || self.open_edition,
+            "Total supply can only be increased on an open edition"
+        );
+
+        CardEvent::TotalSupplyUpdate([ValueUpdate {
+            old_value: self.total_supply.into(),
+            new_value: total_supply.into(),
+        }])
+        .emit();
+        self.total_supply = total_supply;
+    }
+
+    pub fn get_cost_per_token(&self) -> U128 {
+        U128(self.cost_per_token)
+    }
+
+    pub fn get_total_supply(&self) -> U128 {
+        U128(self.total_supply)
+    }
+
+    pub fn is_open_edition(&self) -> bool {
+        self.open_edition
+    }
+
+    pub(crate) fn assert_owner(&self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.tokens.owner_id,
+            "Only the contract owner can call this method"
+        );
     }
 
     pub(crate) fn internal_add_token_to_owner(
@@ -138,7 +244,6 @@ impl TokenizedCard {
     }
 }
 
-near_contract_standards::impl_non_fungible_token_core!(TokenizedCard, tokens);
 near_contract_standards::impl_non_fungible_token_approval!(TokenizedCard, tokens);
 near_contract_standards::impl_non_fungible_token_enumeration!(TokenizedCard, tokens);
 
@@ -160,20 +265,258 @@ pub(crate) fn hash_account_id(account_id: &AccountId) -> CryptoHash {
 
 mod tests {
     use super::*;
-    use near_sdk::test_utils::VMContextBuilder;
+    use near_contract_standards::non_fungible_token::approval::NonFungibleTokenApproval;
+    use near_contract_standards::non_fungible_token::core::NonFungibleTokenCore;
+    use near_contract_standards::non_fungible_token::metadata::TokenMetadata;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::{get_created_receipts, get_logs, VMContextBuilder};
     use near_sdk::testing_env;
 
     const ACCOUNT: &str = "akileus0";
+    const ONE_NEAR: u128 = 1_000_000_000_000_000_000_000_000;
+    const OPERATOR: &str = "market";
+    const RECEIVER: &str = "buyer";
+
+    fn metadata() -> NFTContractMetadata {
+        NFTContractMetadata {
+            spec: "nft-1.0.0".to_string(),
+            name: "tokenized".to_string(),
+            symbol: "TK".to_string(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        }
+    }
+
+    fn owner_context() -> VMContextBuilder {
+        let mut context = VMContextBuilder::new();
+        context
+            .predecessor_account_id(ACCOUNT.parse().unwrap())
+            .attached_deposit(1);
+        context
+    }
 
-    #[test]
     #[test]
     fn initializes() {
-        let contract = TokenizedCard::new(
-            ACCOUNT.parse().unwrap(),
-            [ "nft-1.0.0".clone(), "tokenized".clone(), "TK".clone() ],
-            100,
-            1,
+        let contract = TokenizedCard::new(ACCOUNT.parse().unwrap(), metadata(), U128(100), U128(1), None);
+        assert_eq!(contract.tokens.owner_id, ACCOUNT.parse().unwrap());
+        assert_eq!(contract.get_total_supply(), U128(100));
+        assert_eq!(contract.get_cost_per_token(), U128(1));
+    }
+
+    #[test]
+    fn owner_reprices_and_lowers_supply() {
+        testing_env!(owner_context().build());
+        let mut contract = TokenizedCard::new(ACCOUNT.parse().unwrap(), metadata(), U128(100), U128(1), None);
+
+        contract.set_cost_per_token(U128(5));
+        contract.set_total_supply(U128(50));
+        assert_eq!(contract.get_cost_per_token(), U128(5));
+        assert_eq!(contract.get_total_supply(), U128(50));
+    }
+
+    #[test]
+    #[should_panic(expected = "Total supply can only be increased on an open edition")]
+    fn limited_edition_supply_cannot_grow() {
+        testing_env!(owner_context().build());
+        let mut contract = TokenizedCard::new(ACCOUNT.parse().unwrap(), metadata(), U128(100), U128(1), None);
+
+        contract.set_total_supply(U128(101));
+    }
+
+    #[test]
+    fn buy_stops_at_total_supply() {
+        let mut context = owner_context();
+        testing_env!(context.build());
+        let mut contract =
+            TokenizedCard::new(ACCOUNT.parse().unwrap(), metadata(), U128(2), U128(ONE_NEAR), None);
+
+        testing_env!(context.attached_deposit(5 * ONE_NEAR).build());
+        assert_eq!(contract.buy(ACCOUNT.parse().unwrap()), 2);
+        assert_eq!(contract.tokens.owner_by_id.len(), 2);
+    }
+
+    // NEAR transfers scheduled so far, as (receiver, amount)
+    fn transfers() -> Vec<(String, Balance)> {
+        get_created_receipts()
+            .into_iter()
+            .flat_map(|receipt| {
+                let receiver_id = receipt.receiver_id.to_string();
+                receipt.actions.into_iter().filter_map(move |action| match action {
+                    VmAction::Transfer { deposit } => Some((receiver_id.clone(), deposit)),
+                    _ => None,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn buy_charges_storage_to_prepaid_balance() {
+        let mut context = owner_context();
+        testing_env!(context.build());
+        let mut contract =
+            TokenizedCard::new(ACCOUNT.parse().unwrap(), metadata(), U128(100), U128(ONE_NEAR), None);
+
+        context.predecessor_account_id(RECEIVER.parse().unwrap());
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.storage_deposit(None, None);
+        let prepaid = contract
+            .storage_balance_of(RECEIVER.parse().unwrap())
+            .unwrap()
+            .total
+            .0;
+
+        // the deposit covers the price of 2 tokens and a bit more, the storage comes from the balance
+        testing_env!(context.attached_deposit(2 * ONE_NEAR + ONE_NEAR / 2).build());
+        let initial_storage_usage = env::storage_usage();
+        assert_eq!(contract.buy(RECEIVER.parse().unwrap()), 2);
+        let token_storage_cost =
+            env::storage_byte_cost() * Balance::from(env::storage_usage() - initial_storage_usage) / 2;
+        let remaining = contract
+            .storage_balance_of(RECEIVER.parse().unwrap())
+            .unwrap()
+            .total
+            .0;
+
+        // each token costs exactly its storage from the balance and the price from the deposit
+        assert!(token_storage_cost > 0);
+        assert_eq!(prepaid - remaining, 2 * token_storage_cost);
+        // the owner is paid the price once and the rest of the deposit is refunded once
+        assert_eq!(
+            transfers(),
+            vec![
+                (ACCOUNT.to_string(), 2 * ONE_NEAR),
+                (RECEIVER.to_string(), ONE_NEAR / 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn registration_costs_the_min_storage_balance() {
+        testing_env!(owner_context().build());
+        let mut contract = TokenizedCard::new(ACCOUNT.parse().unwrap(), metadata(), U128(100), U128(1), None);
+        let min = contract.storage_balance_bounds().min.0;
+
+        // the longest account ID uses up the whole min, nothing is left over or refunded
+        let longest_account_id = "a".repeat(64);
+        testing_env!(owner_context()
+            .predecessor_account_id(longest_account_id.parse().unwrap())
+            .attached_deposit(min)
+            .build());
+        contract.storage_deposit(None, None);
+        assert_eq!(
+            contract.storage_balance_of(longest_account_id.parse().unwrap()).unwrap().total.0,
+            0
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "yoctoNEAR to cover storage")]
+    fn registration_requires_the_min_storage_balance() {
+        testing_env!(owner_context().build());
+        let mut contract = TokenizedCard::new(ACCOUNT.parse().unwrap(), metadata(), U128(100), U128(1), None);
+        let min = contract.storage_balance_bounds().min.0;
+
+        testing_env!(owner_context().attached_deposit(min - 1).build());
+        contract.storage_deposit(None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Metadata of token TokenizedCard-1 is frozen")]
+    fn frozen_token_metadata_cannot_be_updated() {
+        let mut context = owner_context();
+        testing_env!(context.build());
+        let mut contract = TokenizedCard::new(ACCOUNT.parse().unwrap(), metadata(), U128(100), U128(1), None);
+        let token_id = "TokenizedCard-1".to_string();
+        contract.internal_add_token_to_owner(&ACCOUNT.parse().unwrap(), token_id.clone());
+
+        let token_metadata = TokenMetadata {
+            title: Some("PSA 9".to_string()),
+            description: None,
+            media: None,
+            media_hash: None,
+            copies: None,
+            issued_at: None,
+            expires_at: None,
+            starts_at: None,
+            updated_at: None,
+            extra: None,
+            reference: None,
+            reference_hash: None,
+        };
+        contract.freeze_token_metadata(token_id.clone());
+
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.nft_update_token_metadata(token_id, token_metadata);
+    }
+
+    fn contract_with_token(context: &mut VMContextBuilder) -> (TokenizedCard, TokenId) {
+        testing_env!(context.build());
+        let mut contract = TokenizedCard::new(ACCOUNT.parse().unwrap(), metadata(), U128(100), U128(1), None);
+        let token_id = "TokenizedCard-1".to_string();
+        contract.internal_add_token_to_owner(&ACCOUNT.parse().unwrap(), token_id.clone());
+        (contract, token_id)
+    }
+
+    fn operator_context() -> VMContextBuilder {
+        let mut context = VMContextBuilder::new();
+        context
+            .predecessor_account_id(OPERATOR.parse().unwrap())
+            .attached_deposit(1);
+        context
+    }
+
+    #[test]
+    fn operator_approved_for_all_can_transfer() {
+        let mut context = owner_context();
+        let (mut contract, token_id) = contract_with_token(&mut context);
+
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.nft_approve_all(OPERATOR.parse().unwrap(), None);
+
+        testing_env!(operator_context().build());
+        contract.nft_transfer(RECEIVER.parse().unwrap(), token_id.clone(), None, None);
+        assert_eq!(
+            contract.tokens.owner_by_id.get(&token_id),
+            Some(RECEIVER.parse().unwrap())
         );
-        assert_eq!(contract.beneficiary, BENEFICIARY.parse().unwrap())
+
+        // the transfer event names the operator that moved the token
+        let transfer_log = get_logs().pop().unwrap();
+        assert!(transfer_log.contains(&format!(r#""old_owner_id":"{}""#, ACCOUNT)));
+        assert!(transfer_log.contains(&format!(r#""authorized_id":"{}""#, OPERATOR)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Sender not approved")]
+    fn revoked_operator_cannot_transfer() {
+        let mut context = owner_context();
+        let (mut contract, token_id) = contract_with_token(&mut context);
+
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.nft_approve_all(OPERATOR.parse().unwrap(), None);
+        testing_env!(context.attached_deposit(1).build());
+        contract.nft_revoke_approve_all(OPERATOR.parse().unwrap());
+
+        assert!(!contract.nft_is_approved_for_all(ACCOUNT.parse().unwrap(), OPERATOR.parse().unwrap()));
+        testing_env!(operator_context().build());
+        contract.nft_transfer(RECEIVER.parse().unwrap(), token_id, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "is different from the given approval_id")]
+    fn token_approval_still_checks_approval_id() {
+        let mut context = owner_context();
+        let (mut contract, token_id) = contract_with_token(&mut context);
+
+        // the operator is approved for all and for the token: the token approval takes precedence
+        testing_env!(context.attached_deposit(ONE_NEAR).build());
+        contract.nft_approve_all(OPERATOR.parse().unwrap(), None);
+        contract.nft_approve(token_id.clone(), OPERATOR.parse().unwrap(), None);
+
+        testing_env!(operator_context().build());
+        contract.nft_transfer(RECEIVER.parse().unwrap(), token_id, Some(42), None);
     }
 }
@@ -0,0 +1,102 @@
+use crate::*;
+use near_contract_standards::non_fungible_token::metadata::TokenMetadata;
+use near_contract_standards::non_fungible_token::refund_deposit;
+use near_sdk::require;
+
+#[near_bindgen]
+impl TokenizedCard {
+    /// Grants `account_id` the right to edit token metadata, e.g. after a card is re-graded
+    #[payable]
+    pub fn add_metadata_editor(&mut self, account_id: AccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.metadata_editors.insert(&account_id);
+    }
+
+    #[payable]
+    pub fn remove_metadata_editor(&mut self, account_id: AccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        self.metadata_editors.remove(&account_id);
+    }
+
+    /// Replaces the metadata of `token_id`. The caller pays for any extra storage the new metadata takes.
+    #[payable]
+    pub fn nft_update_token_metadata(&mut self, token_id: TokenId, metadata: TokenMetadata) {
+        require!(
+            env::attached_deposit() >= 1,
+            "Requires attached deposit of at least 1 yoctoNEAR"
+        );
+        self.assert_metadata_editor();
+        require!(!self.metadata_frozen, "Metadata is frozen");
+        require!(
+            !self.frozen_token_metadata.contains(&token_id),
+            format!("Metadata of token {} is frozen", token_id)
+        );
+        require!(
+            self.tokens.owner_by_id.get(&token_id).is_some(),
+            "Token not found"
+        );
+
+        let metadata = TokenMetadata {
+            updated_at: Some((env::block_timestamp() / 1_000_000).to_string()),
+            ..metadata
+        };
+
+        let initial_storage_usage = env::storage_usage();
+        if let Some(token_metadata_by_id) = &mut self.tokens.token_metadata_by_id {
+            token_metadata_by_id.insert(&token_id, &metadata);
+        }
+        refund_deposit(env::storage_usage().saturating_sub(initial_storage_usage));
+
+        CardEvent::NftMetadataUpdate([TokenIds {
+            token_ids: vec![token_id],
+        }])
+        .emit();
+    }
+
+    /// Permanently locks the metadata of `token_id`
+    #[payable]
+    pub fn freeze_token_metadata(&mut self, token_id: TokenId) {
+        assert_one_yocto();
+        self.assert_metadata_editor();
+        require!(
+            self.tokens.owner_by_id.get(&token_id).is_some(),
+            "Token not found"
+        );
+
+        if self.frozen_token_metadata.insert(&token_id) {
+            CardEvent::MetadataFreeze([TokenIds {
+                token_ids: vec![token_id],
+            }])
+            .emit();
+        }
+    }
+
+    /// Permanently locks the metadata of every token, present and future
+    #[payable]
+    pub fn freeze_metadata(&mut self) {
+        assert_one_yocto();
+        self.assert_owner();
+
+        if !self.metadata_frozen {
+            self.metadata_frozen = true;
+            CardEvent::MetadataFreeze([TokenIds { token_ids: vec![] }]).emit();
+        }
+    }
+
+    pub fn is_metadata_editor(&self, account_id: AccountId) -> bool {
+        account_id == self.tokens.owner_id || self.metadata_editors.contains(&account_id)
+    }
+
+    pub fn is_token_metadata_frozen(&self, token_id: TokenId) -> bool {
+        self.metadata_frozen || self.frozen_token_metadata.contains(&token_id)
+    }
+
+    pub(crate) fn assert_metadata_editor(&self) {
+        require!(
+            self.is_metadata_editor(env::predecessor_account_id()),
+            "Only a metadata editor can call this method"
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use crate::*;
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::json_types::U128;
+use near_sdk::require;
+
+/// Bytes a storage balance takes up for the longest account ID: the record overhead, the key
+/// (the one byte prefix, then the length and the account ID) and the balance.
+const MAX_REGISTRATION_BYTES: u64 = 40 + 1 + 4 + 64 + 16;
+
+/// NEP-145 storage management. Buyers may prepay the storage of the tokens they'll `buy`,
+/// so that the attached deposit only has to cover `cost_per_token`.
+#[near_bindgen]
+impl StorageManagement for TokenizedCard {
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let mut amount = env::attached_deposit();
+
+        let initial_storage_usage = env::storage_usage();
+        let balance = self.storage_deposits.get(&account_id);
+        self.storage_deposits
+            .insert(&account_id, &balance.unwrap_or(0));
+        // the first deposit also pays for the balance entry itself, at least the min of
+        // `storage_balance_bounds` has to be attached for it
+        let registration_cost =
+            env::storage_byte_cost() * Balance::from(env::storage_usage() - initial_storage_usage);
+        if balance.is_none() {
+            let min = self.storage_balance_bounds().min.0;
+            require!(
+                amount >= min,
+                format!("Must attach {} yoctoNEAR to cover storage", min)
+            );
+        }
+        amount -= registration_cost;
+
+        if registration_only == Some(true) {
+            if amount > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(amount);
+            }
+            amount = 0;
+        }
+
+        let balance = balance.unwrap_or(0) + amount;
+        self.storage_deposits.insert(&account_id, &balance);
+        storage_balance(balance)
+    }
+
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let balance = self.storage_deposits.get(&account_id).unwrap_or_else(|| {
+            env::panic_str(&format!("The account {} is not registered", account_id))
+        });
+
+        let amount = amount.map(|amount| amount.0).unwrap_or(balance);
+        require!(
+            amount <= balance,
+            "The amount is greater than the available storage balance"
+        );
+
+        let balance = balance - amount;
+        self.storage_deposits.insert(&account_id, &balance);
+        if amount > 0 {
+            Promise::new(account_id).transfer(amount);
+        }
+        storage_balance(balance)
+    }
+
+    /// Tokens don't depend on the storage balance, so `force` makes no difference
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        let _ = force;
+        let account_id = env::predecessor_account_id();
+
+        let initial_storage_usage = env::storage_usage();
+        match self.storage_deposits.remove(&account_id) {
+            Some(balance) => {
+                let released = env::storage_byte_cost()
+                    * Balance::from(initial_storage_usage - env::storage_usage());
+                Promise::new(account_id).transfer(balance + released);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// `min` is what registering the longest account ID costs, any other account costs less
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(env::storage_byte_cost() * Balance::from(MAX_REGISTRATION_BYTES)),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.storage_deposits.get(&account_id).map(storage_balance)
+    }
+}
+
+fn storage_balance(balance: Balance) -> StorageBalance {
+    StorageBalance {
+        total: U128(balance),
+        available: U128(balance),
+    }
+}